@@ -0,0 +1,73 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const TEMP_SUFFIX: &str = ".rsml-tmp";
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}{}", file_name, TEMP_SUFFIX))
+}
+
+/// Whether the path is a temporary file which is renamed into place once written.
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|file_name| file_name.to_string_lossy())
+        .is_some_and(|file_name| file_name.starts_with('.') && file_name.ends_with(TEMP_SUFFIX))
+}
+
+/// Writes the file by renaming a temporary file in the same directory over it,
+/// so that readers such as Rojo never see a partially written file.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path = temp_path(path);
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_through_a_temporary_file() {
+        let temp = std::env::temp_dir().join("rsml_test_atomic_write");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let path = temp.join("button.model.json");
+        fs::write(&path, "old").unwrap();
+
+        write_atomic(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&temp).unwrap().count(), 1);
+
+        assert!(is_temp_file(&temp_path(&path)));
+        assert!(!is_temp_file(&path));
+
+        // Nothing is left behind when the write fails.
+        let missing_dir = temp.join("missing/button.model.json");
+        assert!(write_atomic(&missing_dir, "new").is_err());
+
+        let blocked = temp.join("blocked");
+        fs::create_dir_all(blocked.join("child")).unwrap();
+        assert!(write_atomic(&blocked, "new").is_err());
+        assert!(!temp_path(&blocked).exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+}
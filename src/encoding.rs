@@ -0,0 +1,111 @@
+use std::{fmt, fs, io, path::Path};
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16_BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// The encoding an `.rsml` file was read with, which is detected from its byte order mark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl fmt::Display for SourceEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SourceEncoding::Utf8 => "UTF-8",
+            SourceEncoding::Utf8Bom => "UTF-8 with a byte order mark",
+            SourceEncoding::Utf16Le => "UTF-16 (little endian)",
+            SourceEncoding::Utf16Be => "UTF-16 (big endian)",
+        })
+    }
+}
+
+impl SourceEncoding {
+    /// Encodes the source the same way it was read, including its byte order mark.
+    pub fn encode(&self, source: &str) -> Vec<u8> {
+        match self {
+            SourceEncoding::Utf8 => source.as_bytes().to_vec(),
+            SourceEncoding::Utf8Bom => [UTF8_BOM, source.as_bytes()].concat(),
+            SourceEncoding::Utf16Le => UTF16_LE_BOM
+                .iter()
+                .copied()
+                .chain(source.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            SourceEncoding::Utf16Be => UTF16_BE_BOM
+                .iter()
+                .copied()
+                .chain(source.encode_utf16().flat_map(u16::to_be_bytes))
+                .collect(),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| from_bytes([unit[0], unit[1]]));
+
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .ok()
+}
+
+/// Decodes the source, stripping its byte order mark. Returns `None` if it isn't valid.
+pub fn decode(bytes: &[u8]) -> Option<(String, SourceEncoding)> {
+    if let Some(bytes) = bytes.strip_prefix(UTF8_BOM) {
+        let source = String::from_utf8(bytes.to_vec()).ok()?;
+        return Some((source, SourceEncoding::Utf8Bom));
+    }
+
+    if let Some(bytes) = bytes.strip_prefix(UTF16_LE_BOM) {
+        let source = decode_utf16(bytes, u16::from_le_bytes)?;
+        return Some((source, SourceEncoding::Utf16Le));
+    }
+
+    if let Some(bytes) = bytes.strip_prefix(UTF16_BE_BOM) {
+        let source = decode_utf16(bytes, u16::from_be_bytes)?;
+        return Some((source, SourceEncoding::Utf16Be));
+    }
+
+    let source = String::from_utf8(bytes.to_vec()).ok()?;
+    Some((source, SourceEncoding::Utf8))
+}
+
+/// Reads the `.rsml` file, decoding it from whichever encoding it's in.
+pub fn read_source(path: &Path) -> io::Result<(String, SourceEncoding)> {
+    let bytes = fs::read(path)?;
+
+    decode(&bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "it isn't valid UTF-8 or UTF-16"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_and_encodes_byte_order_marks() {
+        let source = "Frame {\n    Name = \"wörld\";\n}\n";
+
+        for encoding in [
+            SourceEncoding::Utf8,
+            SourceEncoding::Utf8Bom,
+            SourceEncoding::Utf16Le,
+            SourceEncoding::Utf16Be,
+        ] {
+            let bytes = encoding.encode(source);
+            assert_eq!(decode(&bytes), Some((source.to_string(), encoding)));
+        }
+
+        assert_eq!(decode(b"\xFF\xFEa"), None);
+        assert_eq!(decode(b"Frame \xFF"), None);
+    }
+}
@@ -0,0 +1,2334 @@
+//! Compiles RSML stylesheets into Rojo `.model.json` files.
+//!
+//! - [`compile_source`] compiles a source string on its own.
+//! - [`compile_file`] compiles a file, resolving its derives and Luaurc aliases.
+//! - [`WatcherContext::builder`] opens a project, which [`WatcherContext::initialize`]
+//!   scans, writing every output, and [`Watcher`] keeps up to date. Listeners added with
+//!   [`WatcherContext::add_listener`] are called whenever an output is compiled or removed,
+//!   and with any [`Diagnostic`] found along the way.
+
+mod normalize_path;
+use normalize_path::NormalizePath;
+
+mod rsml_to_model_json;
+use rsml_to_model_json::compile_rsml;
+pub use rsml_to_model_json::{
+    Child, RsmlOutput, StyleDerive, StyleRule, StyleSheet, compile_source,
+};
+
+mod guarded_unwrap;
+use guarded_unwrap::guarded_unwrap;
+
+mod style_root;
+use style_root::{StyleRoot, check_roots};
+
+mod serve;
+pub use serve::ServeState;
+
+mod write_back;
+
+mod poll;
+
+mod atomic_write;
+use atomic_write::{is_temp_file, write_atomic};
+
+mod manifest;
+use manifest::{GeneratedOutput, MANIFEST_FILE_NAME, content_hash};
+
+mod encoding;
+
+mod symlinks;
+pub use symlinks::SymlinkPolicy;
+use symlinks::{SymlinkMap, is_symlink, is_symlink_loop, is_through_symlink};
+
+pub use rbx_rsml::types::LanguageMode;
+use serde::Deserialize;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, Sender, select};
+use jod_thread::JoinHandle;
+use memofs::{ReadDir, StdBackend, Vfs, VfsEvent};
+
+use crate::multibimap::MultiBiMap;
+
+mod diagnostic;
+pub use diagnostic::{Diagnostic, Severity};
+
+mod luaurc;
+use luaurc::{LuaurcChain, copy_language_mode, is_luaurc_path};
+
+mod multibimap;
+
+#[derive(Deserialize)]
+pub(crate) struct ModelJsonId {
+    id: String,
+}
+
+// Removes the directory if it's empty once its empty descendants are removed.
+fn remove_empty_dirs(dir: &Path) {
+    let entries = guarded_unwrap!(fs::read_dir(dir), return);
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path);
+        }
+    }
+
+    let _ = fs::remove_dir(dir);
+}
+
+// Finds the `.model.json` files generated by versions of the CLI which didn't write a manifest.
+fn find_legacy_outputs(dir: &Path) -> Vec<PathBuf> {
+    let mut outputs = Vec::new();
+    let entries = guarded_unwrap!(fs::read_dir(dir), return outputs);
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        // Files linked into the output are never ours to remove.
+        if is_symlink(&path) {
+            continue;
+        }
+
+        if path.is_dir() {
+            outputs.extend(find_legacy_outputs(&path));
+        } else if path.to_string_lossy().ends_with(".model.json") && model_json_is_rsml(&path) {
+            outputs.push(path);
+        }
+    }
+
+    outputs
+}
+
+fn model_json_is_rsml(path: &Path) -> bool {
+    let contents = guarded_unwrap!(fs::read_to_string(path), return false);
+    let model: ModelJsonId = guarded_unwrap!(serde_json::from_str(&contents), return false);
+
+    model.id.ends_with(".rsml")
+}
+
+pub(crate) trait FindFirstChild {
+    fn find_first_child<F>(&self, predicate: F) -> Option<PathBuf>
+    where
+        F: FnMut(&PathBuf) -> bool;
+}
+
+impl FindFirstChild for Path {
+    fn find_first_child<F>(&self, mut predicate: F) -> Option<PathBuf>
+    where
+        F: FnMut(&PathBuf) -> bool,
+    {
+        for entry in fs::read_dir(self).ok()? {
+            let entry = entry.ok()?;
+            let path = entry.path();
+            if predicate(&path) {
+                return Some(path);
+            }
+        }
+
+        None
+    }
+}
+
+impl FindFirstChild for PathBuf {
+    fn find_first_child<F>(&self, predicate: F) -> Option<PathBuf>
+    where
+        F: FnMut(&PathBuf) -> bool,
+    {
+        self.as_path().find_first_child(predicate)
+    }
+}
+
+impl FindFirstChild for &PathBuf {
+    fn find_first_child<F>(&self, predicate: F) -> Option<PathBuf>
+    where
+        F: FnMut(&PathBuf) -> bool,
+    {
+        self.as_path().find_first_child(predicate)
+    }
+}
+
+/// A change to the outputs of the watcher.
+pub enum OutputEvent<'a> {
+    /// The `.rsml` file at `path` was compiled.
+    Compiled {
+        path: &'a Path,
+        id: &'a str,
+        model_json: &'a str,
+    },
+
+    /// The `.rsml` file or directory at `path` no longer has any outputs.
+    Removed { path: &'a Path },
+
+    /// A problem was found, such as a derive which couldn't be resolved.
+    Diagnostic(&'a Diagnostic),
+}
+
+pub type OutputListener = Box<dyn FnMut(&OutputEvent) + Send>;
+
+pub struct WatcherContext {
+    pub(crate) vfs: Arc<Vfs>,
+
+    /// The input directory is always the first root.
+    pub(crate) roots: Vec<StyleRoot>,
+
+    pub(crate) dependencies: MultiBiMap<PathBuf, PathBuf>,
+
+    /// Files mapped to the paths their unresolved derives were expected at.
+    pub(crate) unresolved_derives: MultiBiMap<PathBuf, PathBuf>,
+
+    pub(crate) luaurcs: LuaurcChain,
+
+    /// Overrides the language mode from directives and Luaurc files when specified.
+    pub(crate) language_mode: Option<LanguageMode>,
+
+    /// Files which weren't compiled because of errors in strict mode.
+    pub failed: BTreeSet<PathBuf>,
+
+    /// Outputs which weren't written as a file the CLI didn't generate is in
+    /// the way, mapped to the `.rsml` file they would have been compiled from.
+    pub(crate) conflicts: BTreeMap<PathBuf, PathBuf>,
+
+    /// Overwrites files which weren't generated by the CLI.
+    pub(crate) force: bool,
+
+    pub(crate) symlink_policy: SymlinkPolicy,
+
+    /// Warns about `.rsml` files which aren't plain UTF-8, such as UTF-16 files.
+    pub(crate) warn_encoding: bool,
+
+    /// Symlinked directories which were followed while scanning.
+    symlinks: SymlinkMap,
+
+    /// Output directories created by the CLI, whose events are ignored.
+    created_dirs: HashSet<PathBuf>,
+
+    /// Outputs mapped to the file they were generated from, so they can be
+    /// regenerated if they're edited or removed by something else.
+    pub(crate) generated: BTreeMap<PathBuf, GeneratedOutput>,
+
+    /// Output directories which are watched for changes to the generated files.
+    watched_output_dirs: HashSet<PathBuf>,
+
+    /// Output directories without a manifest, whose generated files are found by their contents.
+    legacy_output_dirs: HashSet<PathBuf>,
+
+    /// Output directories mapped to the manifest which was last written to them.
+    saved_manifests: HashMap<PathBuf, String>,
+
+    listeners: Vec<OutputListener>,
+
+    /// Polls for changes at the interval rather than using native file notifications.
+    poll: Option<Duration>,
+
+    pub stats: SessionStats,
+}
+
+/// The work done during a session, which is summarised when it ends.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    /// How many times a file was compiled.
+    pub rebuilds: usize,
+
+    /// How many compiles failed because of errors in strict mode.
+    pub errors: usize,
+
+    pub compile_time: Duration,
+}
+
+impl WatcherContext {
+    #[cfg(test)]
+    fn handle_vfs_event(&mut self, event: VfsEvent) {
+        self.handle_vfs_events(vec![event]);
+    }
+
+    /// Applies a batch of events, rebuilding each affected file once.
+    fn handle_vfs_events(&mut self, events: Vec<VfsEvent>) {
+        let mut paths = BTreeSet::new();
+
+        for event in &events {
+            self.vfs
+                .commit_event(event)
+                .expect("Error applying VFS change");
+
+            // Renames and moves arrive as a remove of the old path and a create of the new one.
+            if let VfsEvent::Create(path) | VfsEvent::Write(path) | VfsEvent::Remove(path) = event {
+                // Multiple events for the same path only need handling once.
+                paths.extend(self.event_paths(path));
+            }
+        }
+
+        let mut pending = BTreeSet::new();
+
+        for path in paths {
+            self.handle_path_change(path, &mut pending);
+        }
+
+        self.rebuild(pending);
+        self.save_manifests();
+        self.flush_diagnostics();
+    }
+
+    // Paths inside of a root are kept as they are, so paths through symlinks aren't
+    // resolved away from where they're linked. Other paths, such as those reported
+    // for the target of a symlink, are mapped back to where they're linked.
+    fn event_paths(&self, path: &Path) -> Vec<PathBuf> {
+        let normalized = path.normalize();
+
+        if self.roots.iter().any(|root| {
+            normalized.starts_with(&root.input_dir) || normalized.starts_with(&root.output_dir)
+        }) {
+            return vec![normalized];
+        }
+
+        let canonical = dunce::canonicalize(path).unwrap_or(normalized);
+        let linked_paths = self.symlinks.linked_paths(&canonical);
+
+        match linked_paths.is_empty() {
+            true => vec![canonical],
+            false => linked_paths,
+        }
+    }
+
+    // Files which need compiling are added to `pending` rather than compiled straight away.
+    fn handle_path_change(&mut self, path: PathBuf, pending: &mut BTreeSet<PathBuf>) {
+        if let Some(file_name) = path.file_name()
+            && file_name.to_string_lossy().ends_with(".model.json")
+        {
+            self.output_changed(&path, pending);
+            return;
+        }
+
+        // Our own temporary files are renamed into place as soon as they're written.
+        if is_temp_file(&path) || path.file_name() == Some(OsStr::new(MANIFEST_FILE_NAME)) {
+            return;
+        }
+
+        // Nested output directories never contain inputs, and are kept up to date by us.
+        if self.is_in_nested_output(&path) || self.is_skipped_symlink(&path) {
+            return;
+        }
+
+        // Directories created for our own outputs don't need scanning.
+        if path.is_dir() && self.created_dirs.contains(&path) {
+            return;
+        }
+        self.created_dirs.remove(&path);
+
+        // Luaurc files are handled whether they were changed, created or removed.
+        if is_luaurc_path(&path) || self.luaurcs.is_base(&path) {
+            self.luaurc_update(path, pending);
+            return;
+        }
+
+        let is_rsml_ext = path.extension() == Some(OsStr::new("rsml"));
+
+        if path.is_file() {
+            if is_rsml_ext {
+                pending.insert(path);
+            }
+        } else if path.is_dir() {
+            if self.style_root(&path).is_some() {
+                self.recursive_scan(&path, pending);
+            }
+
+        // path no longer exists, remove it (the Remove event can't be relied upon).
+        } else {
+            if is_rsml_ext {
+                let _ = fs::remove_file(&path);
+
+                // Files deriving the removed file need rebuilding without it.
+                if let Some(dependants) = self.dependencies.get_by_right(&path) {
+                    pending.extend(dependants.iter().map(|dependant| dependant.to_path_buf()));
+                }
+
+                self.dependencies.remove_by_left(path.clone());
+                self.unresolved_derives.remove_by_left(path.clone());
+
+                self.luaurcs.dependants.remove_by_right(path.clone());
+                self.failed.remove(&path);
+                self.conflicts.retain(|_, source| *source != path);
+
+                if let Some(output_path) = self.output_path(&path)
+                    && self.owns(&output_path)
+                {
+                    let _ = fs::remove_file(&output_path);
+                    self.generated.remove(&output_path);
+                }
+                self.notify(OutputEvent::Removed { path: &path });
+
+                pending.remove(&path);
+
+            // We can't decipher if the deleted path is a file or a directory,
+            // so we treat it as if it were a directory. This should be fine as
+            // we are only deleting dependencies whose path begins with this
+            // deleted path - if a match is found then it is indeed a directory.
+            } else {
+                // Files outside of the directory which derive something inside of it
+                // need rebuilding, such as when the directory was moved elsewhere.
+                pending.extend(self.dependants_within(&path));
+
+                self.prune_dependencies(&path);
+                self.remove_outputs_within(&path);
+                self.generated
+                    .retain(|_, generated| !generated.source.starts_with(&path));
+                self.conflicts
+                    .retain(|_, source| !source.starts_with(&path));
+                self.symlinks.remove_within(&path);
+                self.notify(OutputEvent::Removed { path: &path });
+
+                pending.retain(|pending_path| !pending_path.starts_with(&path));
+            }
+        }
+    }
+
+    // Generated outputs which were edited or removed by something else are regenerated.
+    // Any other `.model.json` file, such as one written by hand, is left alone.
+    fn output_changed(&mut self, output_path: &Path, pending: &mut BTreeSet<PathBuf>) {
+        // Outputs which were blocked by another file can be written once it's removed.
+        if let Some(source) = self.conflicts.get(output_path) {
+            if !output_path.exists() {
+                pending.insert(source.clone());
+            }
+            return;
+        }
+
+        let generated = guarded_unwrap!(self.generated.get(output_path), return);
+
+        // Events for our own writes, or outputs whose source is being removed anyway.
+        if generated.is_unchanged(output_path) || !generated.source.is_file() {
+            return;
+        }
+
+        let source = generated.source.clone();
+        self.report(Diagnostic::warning(format!(
+            "{:#?} was edited or removed, so it will be regenerated from {:#?}.",
+            output_path, source
+        )));
+        pending.insert(source);
+    }
+
+    /// Whether the `.model.json` file was generated by the CLI, and so can be replaced or removed.
+    pub(crate) fn owns(&self, output_path: &Path) -> bool {
+        self.generated.contains_key(output_path)
+            || (self.is_legacy_output(output_path) && model_json_is_rsml(output_path))
+    }
+
+    // Trees built before manifests were written fall back to checking the contents of outputs.
+    fn is_legacy_output(&self, output_path: &Path) -> bool {
+        self.legacy_output_dirs
+            .iter()
+            .any(|output_dir| output_path.starts_with(output_dir))
+    }
+
+    // Generated outputs whose source no longer exists. Without a manifest every output
+    // of a `.rsml` file is treated as stale, as the current ones are rebuilt anyway.
+    fn is_stale_output(&self, output_path: &Path) -> bool {
+        match self.generated.get(output_path) {
+            Some(generated) => !generated.source.is_file(),
+            None => self.is_legacy_output(output_path) && model_json_is_rsml(output_path),
+        }
+    }
+
+    fn remove_stale_output(&mut self, output_path: &Path) {
+        if self.is_stale_output(output_path) {
+            let _ = fs::remove_file(output_path);
+            self.generated.remove(output_path);
+        }
+    }
+
+    /// Reads the manifest of every root, recording the roots without one.
+    fn load_manifests(&mut self) {
+        let mut diagnostics = Vec::new();
+
+        for root in &self.roots {
+            match manifest::load(root, &mut diagnostics) {
+                Some(generated) => self.generated.extend(generated),
+                None => {
+                    self.legacy_output_dirs.insert(root.output_dir.clone());
+                }
+            }
+        }
+
+        for diagnostic in diagnostics {
+            self.report(diagnostic);
+        }
+    }
+
+    /// Removes every generated output along with the manifests, returning how many were removed.
+    pub fn clean(&mut self) -> usize {
+        self.load_manifests();
+
+        // Outputs listed in a manifest are only removed if they haven't been edited since.
+        let mut outputs = std::mem::take(&mut self.generated)
+            .into_iter()
+            .map(|(output_path, generated)| (output_path, Some(generated)))
+            .collect::<Vec<_>>();
+
+        // Without a manifest, generated outputs are found by their contents.
+        for output_dir in &self.legacy_output_dirs {
+            outputs.extend(
+                find_legacy_outputs(output_dir)
+                    .into_iter()
+                    .map(|output_path| (output_path, None)),
+            );
+        }
+
+        let mut removed = 0;
+
+        for (output_path, generated) in outputs {
+            if !output_path.exists() {
+                continue;
+            }
+
+            if let Some(generated) = generated
+                && !generated.is_unchanged(&output_path)
+            {
+                self.report(Diagnostic::warning(format!(
+                    "{:#?} was edited since it was generated, so it wasn't removed.",
+                    output_path
+                )));
+                continue;
+            }
+
+            if fs::remove_file(&output_path).is_ok() {
+                removed += 1;
+            }
+
+            // Directories which only held generated files are removed too.
+            let output_dir = self
+                .roots
+                .iter()
+                .map(|root| root.output_dir.as_path())
+                .find(|output_dir| output_path.starts_with(output_dir));
+
+            for ancestor in output_path.ancestors().skip(1) {
+                if Some(ancestor) == output_dir || fs::remove_dir(ancestor).is_err() {
+                    break;
+                }
+            }
+        }
+
+        for root in &self.roots {
+            let _ = fs::remove_file(manifest::manifest_path(root));
+        }
+
+        self.flush_diagnostics();
+        removed
+    }
+
+    /// Writes the manifest of every root whose generated outputs have changed.
+    fn save_manifests(&mut self) {
+        let manifests = self
+            .roots
+            .iter()
+            .filter_map(|root| {
+                let outputs = self
+                    .generated
+                    .iter()
+                    .filter(|(_, generated)| {
+                        self.style_root(&generated.source)
+                            .is_some_and(|source_root| source_root.input_dir == root.input_dir)
+                    })
+                    .collect::<Vec<_>>();
+
+                // Roots without any outputs don't need a manifest.
+                if outputs.is_empty() && !manifest::manifest_path(root).exists() {
+                    return None;
+                }
+
+                Some((root.clone(), manifest::serialize(root, outputs.into_iter())))
+            })
+            .collect::<Vec<_>>();
+
+        for (root, contents) in manifests {
+            if self.saved_manifests.get(&root.output_dir) != Some(&contents) {
+                if let Err(diagnostic) = manifest::save(&root, &contents) {
+                    self.report(diagnostic);
+                }
+                self.saved_manifests.insert(root.output_dir, contents);
+            }
+        }
+    }
+
+    /// The root which the specified path belongs to, preferring the most nested root.
+    pub(crate) fn style_root(&self, path: &Path) -> Option<&StyleRoot> {
+        self.roots
+            .iter()
+            .filter(|root| root.contains(path))
+            .max_by_key(|root| root.input_dir.components().count())
+    }
+
+    fn output_path(&self, path: &Path) -> Option<PathBuf> {
+        self.style_root(path)?.output_path(path)
+    }
+
+    /// Adds a style root whose files are referenced with the id prefix.
+    /// Fails if either directory doesn't exist.
+    pub(crate) fn add_root(
+        &mut self,
+        input_dir: &Path,
+        output_dir: &Path,
+        id_prefix: String,
+    ) -> io::Result<()> {
+        let input_dir = dunce::canonicalize(input_dir)?;
+        let output_dir = dunce::canonicalize(output_dir)?;
+
+        self.roots
+            .push(StyleRoot::new(input_dir, output_dir, id_prefix));
+        Ok(())
+    }
+
+    /// Calls the listener whenever an output is compiled or removed, or a problem is found.
+    pub fn add_listener(&mut self, listener: OutputListener) {
+        self.listeners.push(listener);
+    }
+
+    fn notify(&mut self, event: OutputEvent) {
+        // Problems with Luaurcs are found before the files they apply to are compiled.
+        self.flush_diagnostics();
+
+        for listener in &mut self.listeners {
+            listener(&event);
+        }
+    }
+
+    pub(crate) fn report(&mut self, diagnostic: Diagnostic) {
+        self.notify(OutputEvent::Diagnostic(&diagnostic));
+    }
+
+    // Sends the problems found while reading Luaurcs, which can't notify listeners themselves.
+    fn flush_diagnostics(&mut self) {
+        for diagnostic in std::mem::take(&mut self.luaurcs.diagnostics) {
+            for listener in &mut self.listeners {
+                listener(&OutputEvent::Diagnostic(&diagnostic));
+            }
+        }
+    }
+
+    /// The language mode which the specified file is compiled with, where the command line
+    /// takes precedence over the file's directive, which takes precedence over the Luaurc.
+    pub(crate) fn language_mode(
+        &mut self,
+        path: &Path,
+        directive: Option<LanguageMode>,
+    ) -> LanguageMode {
+        if let Some(language_mode) = &self.language_mode {
+            return copy_language_mode(language_mode);
+        }
+
+        if let Some(language_mode) = directive {
+            return language_mode;
+        }
+
+        let dir = path.parent().unwrap_or(path);
+        self.luaurcs
+            .effective(dir)
+            .language_mode
+            .unwrap_or_default()
+    }
+
+    /// Compiles the specified files along with every file which depends on them,
+    /// compiling each file once and after the files it derives.
+    fn rebuild(&mut self, pending: BTreeSet<PathBuf>) {
+        let mut to_build = BTreeSet::new();
+        let mut queue = pending.into_iter().collect::<Vec<_>>();
+
+        while let Some(path) = queue.pop() {
+            if !to_build.insert(path.clone()) {
+                continue;
+            }
+
+            // Files which previously failed to derive this path can now be rebuilt.
+            if let Some(waiting) = self.unresolved_derives.get_by_right(&path) {
+                queue.extend(waiting.iter().map(|dependant| dependant.to_path_buf()));
+            }
+
+            if let Some(dependants) = self.dependencies.get_by_right(&path) {
+                queue.extend(dependants.iter().map(|dependant| dependant.to_path_buf()));
+            }
+        }
+
+        let start = Instant::now();
+
+        for path in self.dependency_order(to_build) {
+            self.create_file(&path);
+        }
+
+        self.stats.compile_time += start.elapsed();
+    }
+
+    // Orders the files so each one comes after the files it derives.
+    // Files in a derive cycle are ordered arbitrarily amongst themselves.
+    fn dependency_order(&self, mut remaining: BTreeSet<PathBuf>) -> Vec<PathBuf> {
+        let mut ordered = Vec::with_capacity(remaining.len());
+
+        while !remaining.is_empty() {
+            let ready = remaining
+                .iter()
+                .filter(|path| {
+                    self.dependencies
+                        .get_by_left(*path)
+                        .is_none_or(|dependencies| {
+                            dependencies.iter().all(|dependency| {
+                                dependency.as_ref() == *path
+                                    || !remaining.contains(dependency.as_ref())
+                            })
+                        })
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let ready = match ready.is_empty() {
+                true => vec![remaining.first().unwrap().clone()],
+                false => ready,
+            };
+
+            for path in ready {
+                remaining.remove(&path);
+                ordered.push(path);
+            }
+        }
+
+        ordered
+    }
+
+    // Creates the directory along with any missing parents, remembering which were created.
+    fn create_output_dir(&mut self, dir: &Path) {
+        let missing = dir
+            .ancestors()
+            .take_while(|ancestor| !ancestor.exists())
+            .map(|ancestor| ancestor.to_path_buf())
+            .collect::<Vec<_>>();
+
+        if fs::create_dir_all(dir).is_ok() {
+            for missing_dir in &missing {
+                self.watched_output_dirs.remove(missing_dir);
+            }
+            self.created_dirs.extend(missing);
+        }
+
+        // Outputs are watched so that they can be regenerated if they're edited or removed.
+        if self.watched_output_dirs.insert(dir.to_path_buf()) {
+            let _ = self.vfs.read_dir(dir);
+        }
+    }
+
+    fn create_file(&mut self, path: &Path) {
+        // Files outside of every root have nowhere to be written to.
+        let output_path = &guarded_unwrap!(self.output_path(path), return);
+
+        // Stylesheets are identified by their path, which must be valid UTF-8.
+        if path.to_str().is_none() {
+            self.report(Diagnostic::warning(format!(
+                "{:#?} was skipped as its path isn't valid UTF-8. Rename it so it can be compiled.",
+                path
+            )));
+            return;
+        }
+
+        self.create_output_dir(output_path.parent().unwrap());
+
+        // Dependencies are recorded again as the file is compiled.
+        self.dependencies.remove_by_left(path.to_path_buf());
+
+        self.stats.rebuilds += 1;
+
+        // The previous output is kept so the place isn't left without the stylesheet.
+        match compile_rsml(path, self) {
+            RsmlOutput::StyleSheet(style_sheet) => {
+                let model_json = style_sheet.to_model_json();
+
+                // Files the CLI didn't generate, such as hand-written ones, are never overwritten.
+                if output_path.exists() && !self.force && !self.owns(output_path) {
+                    self.report(Diagnostic::error(format!(
+                        "{:#?} was not compiled as {:#?} already exists and wasn't generated by RSML CLI.\n    Rename one of them, or use `--force` to overwrite it.",
+                        path, output_path
+                    )));
+                    self.conflicts
+                        .insert(output_path.clone(), path.to_path_buf());
+                    self.stats.errors += 1;
+                    return;
+                }
+                self.conflicts.remove(output_path);
+
+                if let Err(err) = write_atomic(output_path, &model_json) {
+                    self.report(Diagnostic::error(format!(
+                        "Could not write {:#?}: {}",
+                        output_path, err
+                    )));
+                    self.stats.errors += 1;
+                    return;
+                }
+
+                self.generated.insert(
+                    output_path.clone(),
+                    GeneratedOutput {
+                        source: path.to_path_buf(),
+                        hash: content_hash(model_json.as_bytes()),
+                    },
+                );
+
+                let id = self
+                    .style_root(path)
+                    .and_then(|root| root.id(path))
+                    .unwrap_or_default();
+                self.notify(OutputEvent::Compiled {
+                    path,
+                    id: &id,
+                    model_json: &model_json,
+                });
+            }
+
+            // Removes the output from before the directive was added.
+            RsmlOutput::NoOutput => {
+                if self.owns(output_path) {
+                    let _ = fs::remove_file(output_path);
+                }
+                self.generated.remove(output_path);
+                self.conflicts.remove(output_path);
+
+                self.notify(OutputEvent::Removed { path });
+            }
+
+            // The previous output is kept until the file can be read again.
+            RsmlOutput::Unreadable => {
+                self.stats.errors += 1;
+                return;
+            }
+
+            RsmlOutput::Failed => {
+                self.report(Diagnostic::error(format!(
+                    "{:#?} was not compiled as it has errors in strict mode.",
+                    path
+                )));
+                self.failed.insert(path.to_path_buf());
+                self.stats.errors += 1;
+                return;
+            }
+        };
+        self.failed.remove(path);
+    }
+
+    fn luaurc_update(&mut self, luaurc_path: PathBuf, pending: &mut BTreeSet<PathBuf>) {
+        let change = guarded_unwrap!(self.luaurcs.reload(&luaurc_path), return);
+
+        // Only the files beneath the Luaurc which use a changed alias need updating.
+        pending.extend(self.luaurcs.affected_by(&change));
+
+        // Every file beneath the Luaurc is compiled with its language mode.
+        if change.language_mode && self.language_mode.is_none() {
+            self.rebuild_dir(change.dir.as_deref(), pending);
+        }
+    }
+
+    // Recompiles every file in the directory, or in every root if no directory is specified.
+    fn rebuild_dir(&mut self, dir: Option<&Path>, pending: &mut BTreeSet<PathBuf>) {
+        let dirs = self
+            .roots
+            .iter()
+            .filter_map(|root| match dir {
+                Some(dir) if root.input_dir.starts_with(dir) => Some(root.input_dir.clone()),
+                Some(dir) if root.contains(dir) => Some(dir.to_path_buf()),
+                Some(_) => None,
+                None => Some(root.input_dir.clone()),
+            })
+            .collect::<Vec<_>>();
+
+        for dir in dirs {
+            self.recursive_scan_create(self.vfs.read_dir(dir), pending);
+        }
+    }
+
+    // Files outside of the directory which derive a file inside of it.
+    fn dependants_within(&self, dir: &Path) -> BTreeSet<PathBuf> {
+        self.dependencies
+            .right_to_left
+            .iter()
+            .filter(|(dependency, _)| dependency.starts_with(dir))
+            .flat_map(|(_, dependants)| dependants.iter())
+            .filter(|dependant| !dependant.starts_with(dir))
+            .map(|dependant| dependant.to_path_buf())
+            .collect()
+    }
+
+    // Removes the outputs of a removed input directory when they're kept elsewhere.
+    fn remove_outputs_within(&mut self, dir: &Path) {
+        let root = guarded_unwrap!(self.style_root(dir), return);
+        if root.input_dir == root.output_dir {
+            return;
+        }
+
+        let output_dir = guarded_unwrap!(root.output_dir_for(dir), return);
+
+        self.recursive_scan_clean(self.vfs.read_dir(&output_dir));
+        remove_empty_dirs(&output_dir);
+
+        self.created_dirs
+            .retain(|created_dir| !created_dir.starts_with(&output_dir));
+        self.generated
+            .retain(|generated_path, _| !generated_path.starts_with(&output_dir));
+    }
+
+    // Removes any dependencies which start with the specified path.
+    fn prune_dependencies(&mut self, deleted_path: &Path) {
+        let keys_to_prune_from_dependencies = self
+            .dependencies
+            .left_to_right
+            .keys()
+            .filter_map(|key| {
+                if key.starts_with(deleted_path) {
+                    Some(key.to_path_buf())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for key in keys_to_prune_from_dependencies {
+            self.dependencies.remove_by_left(key);
+        }
+
+        let keys_to_prune_from_unresolved_derives = self
+            .unresolved_derives
+            .left_to_right
+            .keys()
+            .filter_map(|key| {
+                if key.starts_with(deleted_path) {
+                    Some(key.to_path_buf())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for key in keys_to_prune_from_unresolved_derives {
+            self.unresolved_derives.remove_by_left(key);
+        }
+
+        let keys_to_prune_from_luaurc_dependants = self
+            .luaurcs
+            .dependants
+            .right_to_left
+            .keys()
+            .filter_map(|key| {
+                if key.starts_with(deleted_path) {
+                    Some(key.to_path_buf())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for key in keys_to_prune_from_luaurc_dependants {
+            self.luaurcs.dependants.remove_by_right(key);
+        }
+
+        self.failed.retain(|path| !path.starts_with(deleted_path));
+    }
+
+    /// Compiles every `.rsml` file, writing their outputs and removing stale ones.
+    pub fn initialize(&mut self) {
+        let root_dirs = self
+            .roots
+            .iter()
+            .map(|root| root.input_dir.clone())
+            .collect::<Vec<_>>();
+
+        self.load_manifests();
+
+        let mut pending = BTreeSet::new();
+
+        for root_dir in root_dirs {
+            self.luaurcs.discover(&root_dir);
+            self.recursive_scan(&root_dir, &mut pending);
+        }
+
+        self.rebuild(pending);
+
+        // Every generated file is now listed in the manifest.
+        self.legacy_output_dirs.clear();
+        self.save_manifests();
+        self.flush_diagnostics();
+    }
+
+    /// Whether the path is the input directory of a root, in which case
+    /// it's scanned on its own rather than as part of its parent root,
+    /// or an output directory nested inside of an input directory.
+    fn is_root_dir(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| {
+            root.input_dir == path || (root.has_nested_output() && root.output_dir == path)
+        })
+    }
+
+    /// Whether the path is inside of an output directory which is nested inside of
+    /// an input directory, whose events are for outputs rather than inputs.
+    fn is_in_nested_output(&self, path: &Path) -> bool {
+        self.roots
+            .iter()
+            .any(|root| root.has_nested_output() && path.starts_with(&root.output_dir))
+    }
+
+    /// Whether the path is beneath a symlink which is skipped by the symlink policy.
+    fn is_skipped_symlink(&self, path: &Path) -> bool {
+        self.symlink_policy == SymlinkPolicy::Skip
+            && self
+                .style_root(path)
+                .is_some_and(|root| is_through_symlink(path, &root.input_dir))
+    }
+
+    // Whether a path found while scanning an input directory is scanned, which
+    // every path is unless it's a symlink which is skipped or would loop forever.
+    fn follows(&mut self, path: &Path) -> bool {
+        if !is_symlink(path) {
+            return true;
+        }
+
+        if self.symlink_policy == SymlinkPolicy::Skip {
+            return false;
+        }
+
+        if !path.is_dir() {
+            return true;
+        }
+
+        let root = guarded_unwrap!(self.style_root(path), return false);
+        if is_symlink_loop(path, &root.input_dir) {
+            self.report(Diagnostic::warning(format!(
+                "{:#?} was skipped as it links to a directory which contains it.",
+                path
+            )));
+            return false;
+        }
+
+        if let Ok(target) = dunce::canonicalize(path) {
+            self.symlinks.insert(path, target);
+        }
+
+        true
+    }
+
+    // Removes stale outputs and adds every `.rsml` file to `pending`.
+    fn recursive_scan(&mut self, dir: &Path, pending: &mut BTreeSet<PathBuf>) {
+        if !self.follows(dir) {
+            return;
+        }
+
+        let root = guarded_unwrap!(self.style_root(dir), return);
+        let offset_output_dir = &guarded_unwrap!(root.output_dir_for(dir), return);
+        let offset_input_dir = &dir.normalize();
+
+        if root.input_dir == root.output_dir {
+            self.recursive_scan_create_and_clean(self.vfs.read_dir(offset_input_dir), pending);
+        } else {
+            self.recursive_scan_clean(self.vfs.read_dir(offset_output_dir));
+            self.recursive_scan_create(self.vfs.read_dir(offset_input_dir), pending);
+        }
+    }
+
+    fn recursive_scan_create_and_clean(
+        &mut self,
+        dir: Result<ReadDir, std::io::Error>,
+        pending: &mut BTreeSet<PathBuf>,
+    ) {
+        let dir = guarded_unwrap!(dir, return);
+
+        for entry in dir {
+            let path = guarded_unwrap!(&entry, continue).path();
+
+            if !self.follows(path) {
+                continue;
+            }
+
+            // Applies files for all of the directories descendants.
+            if path.is_dir() {
+                if !self.is_root_dir(path) {
+                    self.recursive_scan_create_and_clean(self.vfs.read_dir(path), pending);
+                }
+            } else if path.is_file() {
+                // Creates the .model.json for the current .rsml file.
+                if path.extension() == Some(OsStr::new("rsml")) {
+                    pending.insert(path.to_path_buf());
+
+                // Deletes generated .model.json files whose source no longer exists.
+                } else if path.to_string_lossy().ends_with(".model.json") {
+                    self.remove_stale_output(path);
+
+                // Deletes temporary files left behind by a crash.
+                } else if is_temp_file(path) {
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+    }
+
+    fn recursive_scan_create(
+        &mut self,
+        dir: Result<ReadDir, std::io::Error>,
+        pending: &mut BTreeSet<PathBuf>,
+    ) {
+        let dir = guarded_unwrap!(dir, return);
+
+        for entry in dir {
+            let path = guarded_unwrap!(&entry, continue).path();
+
+            if !self.follows(path) {
+                continue;
+            }
+
+            // Applies files for all of the directories descendants.
+            if path.is_dir() {
+                if !self.is_root_dir(path) {
+                    self.recursive_scan_create(self.vfs.read_dir(path), pending);
+                }
+
+            // Creates the .model.json for the current .rsml file.
+            } else if path.is_file() && path.extension() == Some(OsStr::new("rsml")) {
+                pending.insert(path.to_path_buf());
+            }
+        }
+    }
+
+    fn recursive_scan_clean(&mut self, dir: Result<ReadDir, std::io::Error>) {
+        let dir = guarded_unwrap!(dir, return);
+
+        for entry in dir {
+            let path = guarded_unwrap!(&entry, continue).path();
+
+            // Files linked into the output are never ours to remove.
+            if is_symlink(path) {
+                continue;
+            }
+
+            // Applies files for all of the directories descendants.
+            if path.is_dir() {
+                self.recursive_scan_clean(self.vfs.read_dir(path));
+
+            // Removes stale .model.json files, and temporary files left behind by a crash.
+            } else if path.is_file() && path.to_string_lossy().ends_with(".model.json") {
+                self.remove_stale_output(path);
+            } else if path.is_file() && is_temp_file(path) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// The paths which are polled for changes, which are every root, their
+    /// output directories and the Luaurc files outside of them.
+    fn polled_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self
+            .roots
+            .iter()
+            .map(|root| root.input_dir.clone())
+            .collect::<Vec<_>>();
+
+        // Generated outputs are polled too, so they can be regenerated if they're changed.
+        for root in &self.roots {
+            if !paths.iter().any(|path| root.output_dir.starts_with(path)) {
+                paths.push(root.output_dir.clone());
+            }
+        }
+
+        let luaurc_paths = self
+            .luaurcs
+            .base
+            .iter()
+            .map(|(base_path, _)| base_path)
+            .chain(self.luaurcs.discovered_paths());
+
+        for luaurc_path in luaurc_paths {
+            if self.style_root(luaurc_path).is_none() {
+                paths.push(luaurc_path.clone());
+            }
+        }
+
+        paths
+    }
+
+    /// The events to watch the project with, which come from polling
+    /// the project at the interval when one is specified.
+    fn events(&self) -> Receiver<VfsEvent> {
+        match self.poll {
+            Some(interval) => poll::spawn(self.polled_paths(), interval, self.symlink_policy),
+            None => self.vfs.event_receiver(),
+        }
+    }
+
+    /// Compiles the `.rsml` file without writing its output.
+    pub fn compile(&mut self, path: &Path) -> RsmlOutput {
+        let output = compile_rsml(path, self);
+        self.flush_diagnostics();
+        output
+    }
+
+    /// Outputs which weren't written as a file the CLI didn't generate is in the way.
+    pub fn conflicts(&self) -> Vec<&Path> {
+        self.conflicts.keys().map(PathBuf::as_path).collect()
+    }
+
+    /// Every Luaurc which has been discovered above the compiled files.
+    pub fn discovered_luaurcs(&self) -> Vec<&PathBuf> {
+        self.luaurcs.discovered_paths()
+    }
+
+    /// Starts opening a project which compiles the input directory.
+    pub fn builder(input_dir: impl Into<PathBuf>) -> WatcherContextBuilder {
+        WatcherContextBuilder {
+            input_dir: input_dir.into(),
+            output_dir: None,
+            luaurc_path: None,
+            roots: Vec::new(),
+            language_mode: None,
+            force: false,
+            symlink_policy: SymlinkPolicy::default(),
+            warn_encoding: false,
+            poll: None,
+        }
+    }
+
+    /// Creates a project compiling the input directory into the output directory,
+    /// which can be the same directory. Fails if either directory doesn't exist.
+    pub(crate) fn new(
+        vfs: Vfs,
+        input_dir: &Path,
+        output_dir: &Path,
+        luaurc_path: Option<&PathBuf>,
+    ) -> io::Result<Self> {
+        let input_dir = dunce::canonicalize(input_dir)?;
+        let output_dir = dunce::canonicalize(output_dir)?;
+
+        let vfs = Arc::new(vfs);
+
+        Ok(Self {
+            luaurcs: LuaurcChain::new(vfs.clone(), luaurc_path),
+            vfs,
+            roots: vec![StyleRoot::new(input_dir, output_dir, String::new())],
+            dependencies: MultiBiMap::new(),
+            unresolved_derives: MultiBiMap::new(),
+            language_mode: None,
+            failed: BTreeSet::new(),
+            conflicts: BTreeMap::new(),
+            force: false,
+            symlink_policy: SymlinkPolicy::default(),
+            warn_encoding: false,
+            symlinks: SymlinkMap::default(),
+            created_dirs: HashSet::new(),
+            generated: BTreeMap::new(),
+            watched_output_dirs: HashSet::new(),
+            legacy_output_dirs: HashSet::new(),
+            saved_manifests: HashMap::new(),
+            listeners: Vec::new(),
+            poll: None,
+            stats: SessionStats::default(),
+        })
+    }
+}
+
+/// The options a project is opened with, from [`WatcherContext::builder`].
+pub struct WatcherContextBuilder {
+    input_dir: PathBuf,
+    output_dir: Option<PathBuf>,
+    luaurc_path: Option<PathBuf>,
+    roots: Vec<StyleRoot>,
+    language_mode: Option<LanguageMode>,
+    force: bool,
+    symlink_policy: SymlinkPolicy,
+    warn_encoding: bool,
+    poll: Option<Duration>,
+}
+
+impl WatcherContextBuilder {
+    /// Writes the outputs to the directory, rather than next to their `.rsml` files.
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    /// The Luaurc which applies beneath every automatically found Luaurc.
+    pub fn luaurc(mut self, luaurc_path: impl Into<PathBuf>) -> Self {
+        self.luaurc_path = Some(luaurc_path.into());
+        self
+    }
+
+    /// Adds a style root, whose files are referenced with the id prefix.
+    pub fn root(
+        mut self,
+        input_dir: impl Into<PathBuf>,
+        output_dir: impl Into<PathBuf>,
+        id_prefix: impl Into<String>,
+    ) -> Self {
+        self.roots.push(StyleRoot::new(
+            input_dir.into(),
+            output_dir.into(),
+            id_prefix.into(),
+        ));
+        self
+    }
+
+    /// Compiles every file with the language mode, rather than the one from its directive or Luaurc.
+    pub fn language_mode(mut self, language_mode: Option<LanguageMode>) -> Self {
+        self.language_mode = language_mode;
+        self
+    }
+
+    /// Overwrites files which weren't generated by the CLI.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn symlink_policy(mut self, symlink_policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = symlink_policy;
+        self
+    }
+
+    /// Warns about `.rsml` files which aren't plain UTF-8, such as UTF-16 files.
+    pub fn warn_encoding(mut self, warn_encoding: bool) -> Self {
+        self.warn_encoding = warn_encoding;
+        self
+    }
+
+    /// Polls for changes at the interval instead of using native file notifications.
+    pub fn poll(mut self, interval: Option<Duration>) -> Self {
+        self.poll = interval;
+        self
+    }
+
+    /// Creates the output directories and opens the project, without scanning it.
+    pub fn build(self) -> Result<WatcherContext, String> {
+        let output_dir = self.output_dir.unwrap_or_else(|| self.input_dir.clone());
+
+        // Checked before anything is created, so output directories which don't exist yet are resolved by hand.
+        let roots = std::iter::once(StyleRoot::new(
+            absolute_path(&self.input_dir),
+            absolute_path(&output_dir),
+            String::new(),
+        ))
+        .chain(self.roots.iter().map(|root| {
+            StyleRoot::new(
+                absolute_path(&root.input_dir),
+                absolute_path(&root.output_dir),
+                root.id_prefix.clone(),
+            )
+        }))
+        .collect::<Vec<_>>();
+
+        check_roots(&roots)?;
+
+        for root in &roots {
+            let _ = fs::create_dir_all(&root.input_dir);
+            let _ = fs::create_dir_all(&root.output_dir);
+        }
+
+        let vfs = Vfs::new(StdBackend::new());
+
+        // Polling replaces native notifications, which may never arrive.
+        if self.poll.is_some() {
+            vfs.set_watch_enabled(false);
+        }
+
+        let open_error = |root: &StyleRoot, err: io::Error| {
+            format!(
+                "ERROR: Could not open {:#?} to compile into {:#?}: {}",
+                root.input_dir, root.output_dir, err
+            )
+        };
+
+        let mut context = WatcherContext::new(
+            vfs,
+            &roots[0].input_dir,
+            &roots[0].output_dir,
+            self.luaurc_path.as_ref(),
+        )
+        .map_err(|err| open_error(&roots[0], err))?;
+
+        for root in &roots[1..] {
+            context
+                .add_root(&root.input_dir, &root.output_dir, root.id_prefix.clone())
+                .map_err(|err| open_error(root, err))?;
+        }
+
+        context.language_mode = self.language_mode;
+        context.force = self.force;
+        context.symlink_policy = self.symlink_policy;
+        context.warn_encoding = self.warn_encoding;
+        context.poll = self.poll;
+
+        Ok(context)
+    }
+}
+
+// The canonical path if it exists, otherwise the normalized absolute path.
+fn absolute_path(path: &Path) -> PathBuf {
+    dunce::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(current_dir) => current_dir.join(path).normalize(),
+        Err(_) => path.normalize(),
+    })
+}
+
+/// Handles the events of a [`WatcherContext`] on a background thread.
+pub struct Watcher {
+    shutdown_sender: Sender<()>,
+    job_thread: JoinHandle<WatcherContext>,
+}
+
+impl Watcher {
+    /// How long the file system has to be quiet for before a batch of events is handled.
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    /// The longest a batch of events is held back for while events keep arriving.
+    const MAX_BATCH_DELAY: Duration = Duration::from_millis(500);
+
+    /// Starts handling events on a background thread, which sends to
+    /// `stopped` if it stops by itself, such as when the input directory is removed.
+    pub fn start(context: WatcherContext, stopped: mpsc::Sender<()>) -> Watcher {
+        let vfs_receiver = context.events();
+        Self::start_with_events(context, vfs_receiver, stopped)
+    }
+
+    fn start_with_events(
+        mut context: WatcherContext,
+        vfs_receiver: Receiver<VfsEvent>,
+        stopped: mpsc::Sender<()>,
+    ) -> Watcher {
+        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded::<()>(1);
+
+        let job_thread: JoinHandle<WatcherContext> = jod_thread::Builder::new()
+            .name("ChangeProcessor thread".to_owned())
+            .spawn(move || {
+                loop {
+                    select! {
+                        recv(vfs_receiver) -> event => {
+                            match event {
+                                Ok(event) => {
+                                    // Bursts of events, such as from switching branches,
+                                    // are coalesced so each file is only rebuilt once.
+                                    let mut events = vec![event];
+                                    let batch_start = Instant::now();
+
+                                    while batch_start.elapsed() < Self::MAX_BATCH_DELAY {
+                                        match vfs_receiver.recv_timeout(Self::DEBOUNCE) {
+                                            Ok(event) => events.push(event),
+                                            Err(_) => break,
+                                        }
+                                    }
+
+                                    context.handle_vfs_events(events);
+
+                                    if !context.roots[0].input_dir.exists() {
+                                        let input_dir = context.roots[0].input_dir.clone();
+                                        context.report(Diagnostic::warning(format!(
+                                            "The input directory {:#?} was removed.",
+                                            input_dir
+                                        )));
+                                        let _ = stopped.send(());
+                                        return context;
+                                    }
+                                },
+
+                                // Nothing can send events once every sender is gone.
+                                Err(_) => {
+                                    let _ = stopped.send(());
+                                    return context;
+                                }
+                            }
+                        },
+
+                        recv(shutdown_receiver) -> _ => {
+                            // Events which arrived before shutting down are still handled.
+                            let events = vfs_receiver.try_iter().collect::<Vec<_>>();
+                            if !events.is_empty() {
+                                context.handle_vfs_events(events);
+                            }
+
+                            return context;
+                        }
+                    }
+                }
+            })
+            .expect("Could not start thread");
+
+        Self {
+            job_thread,
+            shutdown_sender,
+        }
+    }
+
+    /// Stops once the batch being handled is finished, returning the context.
+    pub fn stop(self) -> WatcherContext {
+        let _ = self.shutdown_sender.send(());
+        self.job_thread.join()
+    }
+}
+
+/// Compiles the `.rsml` file without writing its output, resolving its derives
+/// relative to its directory and the Luaurc files above it, along with the problems found.
+pub fn compile_file(path: &Path) -> io::Result<(RsmlOutput, Vec<Diagnostic>)> {
+    let path = dunce::canonicalize(path)?;
+    let dir = path.parent().unwrap_or(&path).to_path_buf();
+
+    let vfs = Vfs::new(StdBackend::new());
+    vfs.set_watch_enabled(false);
+
+    let mut context = WatcherContext::new(vfs, &dir, &dir, None)?;
+
+    let diagnostics = Arc::new(Mutex::new(Vec::new()));
+    let listener_diagnostics = diagnostics.clone();
+    context.add_listener(Box::new(move |event| {
+        if let OutputEvent::Diagnostic(diagnostic) = event {
+            listener_diagnostics
+                .lock()
+                .unwrap()
+                .push((*diagnostic).clone());
+        }
+    }));
+
+    context.luaurcs.discover(&dir);
+    let output = context.compile(&path);
+
+    let diagnostics = std::mem::take(&mut *diagnostics.lock().unwrap());
+    Ok((output, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn compiles_sources_and_files_without_writing() {
+        let temp = std::env::temp_dir().join("rsml_test_compile_api");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let source = "Frame {\n    Name = \"Panel\";\n}\n";
+        let style_sheet = compile_source(source, "panel.rsml");
+        assert_eq!(style_sheet.id, "panel.rsml");
+        assert!(matches!(
+            style_sheet.children.as_slice(),
+            [Child::StyleRule(rule)] if rule.name.as_deref() == Some("Frame")
+        ));
+
+        fs::write(temp.join("base.rsml"), "").unwrap();
+        fs::write(
+            temp.join("button.rsml"),
+            format!("@derive \"./base\";\n{}", source),
+        )
+        .unwrap();
+
+        let style_sheet = match compile_file(&temp.join("button.rsml")).unwrap() {
+            (RsmlOutput::StyleSheet(style_sheet), diagnostics) if diagnostics.is_empty() => {
+                style_sheet
+            }
+            _ => panic!("expected a stylesheet without diagnostics"),
+        };
+        assert_eq!(style_sheet.id, "button.rsml");
+        assert!(matches!(
+            style_sheet.children.as_slice(),
+            [Child::StyleRule(_), Child::StyleDerive(derive)] if derive.stylesheet == "base.rsml"
+        ));
+        assert!(
+            style_sheet
+                .to_model_json()
+                .contains("\"className\": \"StyleSheet\"")
+        );
+
+        assert!(!temp.join("button.model.json").exists());
+        assert!(compile_file(&temp.join("missing.rsml")).is_err());
+
+        // Problems are returned rather than printed.
+        fs::write(temp.join("broken.rsml"), "@derive \"./missing\";\n").unwrap();
+        let (_, diagnostics) = compile_file(&temp.join("broken.rsml")).unwrap();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [diagnostic] if diagnostic.severity == Severity::Warning
+                && diagnostic.message.contains("Could not resolve derive")
+        ));
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn builder_creates_outputs_and_checks_roots() {
+        let temp = std::env::temp_dir().join("rsml_test_builder");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("src")).unwrap();
+        fs::write(temp.join("src/button.rsml"), "").unwrap();
+
+        let mut context = WatcherContext::builder(temp.join("src"))
+            .output_dir(temp.join("out"))
+            .build()
+            .unwrap();
+        context.initialize();
+
+        assert!(temp.join("out/button.model.json").exists());
+        assert!(context.conflicts().is_empty());
+
+        // Inputs inside of a separate output directory are refused before anything is created.
+        let nested = WatcherContext::builder(temp.join("out/src"))
+            .output_dir(temp.join("out"))
+            .build();
+        assert!(nested.is_err());
+        assert!(!temp.join("out/src").exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn diagnostics_are_sent_to_listeners() {
+        let temp = std::env::temp_dir().join("rsml_test_diagnostics");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("src")).unwrap();
+        fs::create_dir_all(temp.join("out")).unwrap();
+        fs::write(temp.join("src/.luaurc"), "{").unwrap();
+        fs::write(temp.join("src/button.rsml"), "").unwrap();
+        fs::write(temp.join("out/button.model.json"), "{}").unwrap();
+
+        let mut context = WatcherContext::builder(temp.join("src"))
+            .output_dir(temp.join("out"))
+            .build()
+            .unwrap();
+
+        let diagnostics = Arc::new(Mutex::new(Vec::new()));
+        let listener_diagnostics = diagnostics.clone();
+        context.add_listener(Box::new(move |event| {
+            if let OutputEvent::Diagnostic(diagnostic) = event {
+                listener_diagnostics
+                    .lock()
+                    .unwrap()
+                    .push(diagnostic.to_string());
+            }
+        }));
+        context.initialize();
+
+        // Problems with the Luaurc come before the files it applies to.
+        let diagnostics = diagnostics.lock().unwrap();
+        assert_eq!(diagnostics.len(), 2, "{:#?}", diagnostics);
+        assert!(diagnostics[0].starts_with("ERROR: Could not parse Luaurc"));
+        assert!(diagnostics[1].contains("already exists and wasn't generated by RSML CLI"));
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn build_creates_model_json() {
+        let temp = std::env::temp_dir().join("rsml_test_build");
+        let input = temp.join("src");
+        let output = temp.join("out");
+
+        // Clean up from any previous run.
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&input).unwrap();
+        fs::create_dir_all(&output).unwrap();
+
+        // Write an empty .rsml file (produces a valid model.json).
+        fs::write(input.join("test.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &output, None).unwrap();
+        context.initialize();
+
+        let model_json_path = output.join("test.model.json");
+        assert!(
+            model_json_path.exists(),
+            "Expected {:?} to exist",
+            model_json_path
+        );
+
+        let content: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&model_json_path).unwrap()).unwrap();
+        assert_eq!(content["className"], "StyleSheet");
+
+        // Clean up.
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn watcher_stops_when_events_disconnect() {
+        let temp = std::env::temp_dir().join("rsml_test_watcher_disconnect");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let context = WatcherContext::new(vfs, &temp, &temp, None).unwrap();
+
+        let (event_sender, event_receiver) = crossbeam_channel::unbounded();
+        let (stopped_sender, stopped_receiver) = mpsc::channel();
+        let watcher = Watcher::start_with_events(context, event_receiver, stopped_sender);
+
+        drop(event_sender);
+        assert!(
+            stopped_receiver
+                .recv_timeout(Duration::from_secs(5))
+                .is_ok()
+        );
+        watcher.stop();
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn creating_missing_derive_rebuilds_dependant() {
+        let temp = std::env::temp_dir().join("rsml_test_missing_derive");
+        let input = temp.join("src");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&input).unwrap();
+
+        fs::write(input.join("button.rsml"), "@derive \"./base\";\n").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        let read_children = || {
+            let content: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(input.join("button.model.json")).unwrap())
+                    .unwrap();
+            content["children"].as_array().unwrap().clone()
+        };
+
+        assert!(read_children().is_empty());
+
+        fs::write(input.join("base.rsml"), "").unwrap();
+        context.handle_vfs_event(VfsEvent::Create(input.join("base.rsml")));
+
+        let children = read_children();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0]["className"], "StyleDerive");
+        assert_eq!(
+            children[0]["attributes"]["Rojo_Target_StyleSheet"],
+            "base.rsml"
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn edited_or_removed_outputs_are_regenerated() {
+        let temp = std::env::temp_dir().join("rsml_test_regenerate_outputs");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("src")).unwrap();
+        fs::create_dir_all(temp.join("out")).unwrap();
+
+        let temp = dunce::canonicalize(&temp).unwrap();
+        let (input, output) = (temp.join("src"), temp.join("out"));
+        fs::write(input.join("button.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &output, None).unwrap();
+        context.initialize();
+
+        let button_output = output.join("button.model.json");
+        let generated = fs::read_to_string(&button_output).unwrap();
+
+        fs::write(&button_output, "{}").unwrap();
+        context.handle_vfs_event(VfsEvent::Write(button_output.clone()));
+        assert_eq!(fs::read_to_string(&button_output).unwrap(), generated);
+
+        fs::remove_dir_all(&output).unwrap();
+        context.handle_vfs_events(vec![
+            VfsEvent::Remove(button_output.clone()),
+            VfsEvent::Remove(output.clone()),
+        ]);
+        assert_eq!(fs::read_to_string(&button_output).unwrap(), generated);
+
+        // Files which weren't generated are left alone.
+        let hand_written = output.join("label.model.json");
+        fs::write(&hand_written, "{}").unwrap();
+        context.handle_vfs_event(VfsEvent::Create(hand_written.clone()));
+        assert_eq!(fs::read_to_string(&hand_written).unwrap(), "{}");
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn hand_written_outputs_are_not_overwritten() {
+        let temp = std::env::temp_dir().join("rsml_test_output_conflicts");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        let button_output = input.join("button.model.json");
+        fs::write(&button_output, "{}").unwrap();
+        fs::write(input.join("button.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        assert_eq!(fs::read_to_string(&button_output).unwrap(), "{}");
+        assert_eq!(
+            context.conflicts.get(&button_output),
+            Some(&input.join("button.rsml"))
+        );
+
+        // Removing the file in the way lets the output be written.
+        fs::remove_file(&button_output).unwrap();
+        context.handle_vfs_event(VfsEvent::Remove(button_output.clone()));
+        assert!(context.conflicts.is_empty());
+        assert!(context.owns(&button_output));
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn nested_output_directories_are_not_inputs() {
+        let temp = std::env::temp_dir().join("rsml_test_nested_output");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("out")).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        let output = input.join("out");
+        fs::write(input.join("button.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &output, None).unwrap();
+        context.initialize();
+        assert!(output.join("button.model.json").exists());
+
+        // `.rsml` files inside of the output directory are never compiled.
+        fs::write(output.join("label.rsml"), "").unwrap();
+        context.handle_vfs_event(VfsEvent::Create(output.join("label.rsml")));
+        assert!(!output.join("label.model.json").exists());
+        assert!(!output.join("out").exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directories_keep_their_linked_paths() {
+        let temp = std::env::temp_dir().join("rsml_test_symlinks");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("shared")).unwrap();
+        fs::create_dir_all(temp.join("game/src")).unwrap();
+        fs::create_dir_all(temp.join("game/out")).unwrap();
+
+        let temp = dunce::canonicalize(&temp).unwrap();
+        let (input, output) = (temp.join("game/src"), temp.join("game/out"));
+        fs::write(temp.join("shared/dark.rsml"), "").unwrap();
+
+        std::os::unix::fs::symlink(temp.join("shared"), input.join("themes")).unwrap();
+        std::os::unix::fs::symlink(&input, input.join("loop")).unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &output, None).unwrap();
+        context.initialize();
+
+        let dark_output = output.join("themes/dark.model.json");
+        assert!(
+            fs::read_to_string(&dark_output)
+                .unwrap()
+                .contains("themes/dark.rsml")
+        );
+        assert!(!output.join("loop").exists());
+
+        // Events for the target of the symlink are mapped back to where it's linked.
+        fs::write(temp.join("shared/light.rsml"), "").unwrap();
+        context.handle_vfs_event(VfsEvent::Create(temp.join("shared/light.rsml")));
+        assert!(output.join("themes/light.model.json").exists());
+
+        // Skipped symlinks are never scanned.
+        let _ = fs::remove_dir_all(&output);
+        fs::create_dir_all(&output).unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &output, None).unwrap();
+        context.symlink_policy = SymlinkPolicy::Skip;
+        context.initialize();
+        assert!(!output.join("themes").exists());
+
+        context.handle_vfs_event(VfsEvent::Write(input.join("themes/dark.rsml")));
+        assert!(!output.join("themes").exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn derives_resolve_when_the_project_is_beneath_a_symlink() {
+        let temp = std::env::temp_dir().join("rsml_test_symlinked_project");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("real/src/themes")).unwrap();
+        fs::create_dir_all(temp.join("config")).unwrap();
+
+        // The project is opened through the link, like `/tmp` on macOS.
+        let linked = temp.join("linked");
+        std::os::unix::fs::symlink(temp.join("real"), &linked).unwrap();
+
+        // The alias reaches the project through the link.
+        let luaurc_path = temp.join("config/.luaurc");
+        fs::write(
+            &luaurc_path,
+            "{ \"aliases\": { \"themes\": \"../linked/src/themes\" } }",
+        )
+        .unwrap();
+        fs::write(linked.join("src/themes/dark.rsml"), "").unwrap();
+        fs::write(
+            linked.join("src/button.rsml"),
+            "@derive \"@themes/dark\";\n",
+        )
+        .unwrap();
+
+        let mut context = WatcherContext::builder(linked.join("src"))
+            .luaurc(&luaurc_path)
+            .language_mode(Some(LanguageMode::Strict))
+            .build()
+            .unwrap();
+        context.initialize();
+
+        let button: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(linked.join("src/button.model.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            button["children"][0]["attributes"]["Rojo_Target_StyleSheet"],
+            "themes/dark.rsml"
+        );
+        assert!(context.failed.is_empty());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn manifest_decides_which_outputs_are_stale() {
+        let temp = std::env::temp_dir().join("rsml_test_manifest_cleanup");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        let stylesheet = |id: &str| serde_json::json!({ "className": "StyleSheet", "id": id });
+
+        // A tree built before manifests existed is migrated by checking the contents.
+        fs::write(input.join("button.rsml"), "").unwrap();
+        fs::write(
+            input.join("old.model.json"),
+            stylesheet("old.rsml").to_string(),
+        )
+        .unwrap();
+
+        let build = || {
+            let vfs = Vfs::new(StdBackend::new());
+            let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+            context.initialize();
+            context
+        };
+
+        let context = build();
+        assert!(!input.join("old.model.json").exists());
+        assert!(input.join(MANIFEST_FILE_NAME).exists());
+        assert!(context.owns(&input.join("button.model.json")));
+
+        // With a manifest, only outputs the CLI generated are removed.
+        let hand_written = stylesheet("hand_written.rsml").to_string();
+        fs::write(input.join("hand_written.model.json"), &hand_written).unwrap();
+        fs::remove_file(input.join("button.rsml")).unwrap();
+        drop(context);
+
+        let mut context = build();
+        assert!(!input.join("button.model.json").exists());
+        assert_eq!(
+            fs::read_to_string(input.join("hand_written.model.json")).unwrap(),
+            hand_written
+        );
+        assert!(!context.owns(&input.join("hand_written.model.json")));
+
+        fs::write(input.join("label.rsml"), "").unwrap();
+        context.handle_vfs_event(VfsEvent::Create(input.join("label.rsml")));
+        assert!(
+            fs::read_to_string(input.join(MANIFEST_FILE_NAME))
+                .unwrap()
+                .contains("label.model.json")
+        );
+
+        assert_eq!(context.clean(), 1);
+        assert!(!input.join("label.model.json").exists());
+        assert!(input.join("hand_written.model.json").exists());
+        assert!(!input.join(MANIFEST_FILE_NAME).exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn batched_events_rebuild_in_dependency_order() {
+        let temp = std::env::temp_dir().join("rsml_test_batched_events");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        fs::write(input.join("button.rsml"), "@derive \"./panel\";\n").unwrap();
+        fs::write(input.join("panel.rsml"), "@derive \"./base\";\n").unwrap();
+        fs::write(input.join("base.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        let all = ["button.rsml", "panel.rsml", "base.rsml"]
+            .map(|file| input.join(file))
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            context.dependency_order(all),
+            vec![
+                input.join("base.rsml"),
+                input.join("panel.rsml"),
+                input.join("button.rsml")
+            ]
+        );
+
+        // Duplicate events within a batch are only handled once.
+        fs::remove_file(input.join("button.model.json")).unwrap();
+        context.handle_vfs_events(vec![
+            VfsEvent::Write(input.join("base.rsml")),
+            VfsEvent::Write(input.join("base.rsml")),
+            VfsEvent::Create(input.join("base.rsml")),
+        ]);
+        assert!(input.join("button.model.json").exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn derives_from_external_root_use_its_prefix() {
+        let temp = std::env::temp_dir().join("rsml_test_external_root");
+        let input = temp.join("game/src");
+        let shared = temp.join("shared/styles");
+        let shared_output = temp.join("shared/out");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&input).unwrap();
+        fs::create_dir_all(shared.join("themes")).unwrap();
+        fs::create_dir_all(&shared_output).unwrap();
+
+        fs::write(
+            input.join("button.rsml"),
+            "@derive \"../../shared/styles/themes/dark\";\n",
+        )
+        .unwrap();
+        fs::write(shared.join("themes/dark.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context
+            .add_root(&shared, &shared_output, "shared".to_string())
+            .unwrap();
+        context.initialize();
+
+        let button: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(input.join("button.model.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            button["children"][0]["attributes"]["Rojo_Target_StyleSheet"],
+            "shared/themes/dark.rsml"
+        );
+
+        let dark: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(shared_output.join("themes/dark.model.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            dark["id"],
+            button["children"][0]["attributes"]["Rojo_Target_StyleSheet"]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn luaurc_alias_change_rebuilds_dependants() {
+        let temp = std::env::temp_dir().join("rsml_test_luaurc_alias_change");
+        let input = temp.join("src");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(input.join("themes/dark")).unwrap();
+        fs::create_dir_all(input.join("themes/light")).unwrap();
+
+        let input = dunce::canonicalize(&input).unwrap();
+        let luaurc_path = temp.join(".luaurc");
+        let write_luaurc = |theme: &str| {
+            let luaurc = serde_json::json!({
+                "aliases": {
+                    "theme": input.join("themes").join(theme),
+                    "unused": input.join("themes"),
+                }
+            });
+            fs::write(&luaurc_path, luaurc.to_string()).unwrap();
+        };
+        write_luaurc("dark");
+
+        fs::write(input.join("button.rsml"), "@derive \"@theme/colors\";\n").unwrap();
+        fs::write(
+            input.join("label.rsml"),
+            "@derive \"./themes/dark/colors\";\n",
+        )
+        .unwrap();
+        fs::write(input.join("themes/dark/colors.rsml"), "").unwrap();
+        fs::write(input.join("themes/light/colors.rsml"), "").unwrap();
+
+        let luaurc_path = dunce::canonicalize(&luaurc_path).unwrap();
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, Some(&luaurc_path)).unwrap();
+        context.initialize();
+
+        let derive_target = |file: &str| {
+            let content: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(input.join(file)).unwrap()).unwrap();
+            content["children"][0]["attributes"]["Rojo_Target_StyleSheet"].clone()
+        };
+
+        let dark_id = "themes/dark/colors.rsml";
+        let light_id = "themes/light/colors.rsml";
+        assert_eq!(derive_target("button.model.json"), dark_id);
+
+        // Files which don't use the changed alias shouldn't be rebuilt.
+        fs::write(input.join("label.model.json"), "{}").unwrap();
+
+        write_luaurc("light");
+        context.handle_vfs_event(VfsEvent::Write(luaurc_path.clone()));
+
+        assert_eq!(derive_target("button.model.json"), light_id);
+        assert_eq!(
+            fs::read_to_string(input.join("label.model.json")).unwrap(),
+            "{}"
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn stylesheets_named_like_luaurcs_are_compiled() {
+        let temp = std::env::temp_dir().join("rsml_test_luaurc_named_stylesheet");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        for name in ["luaurc.rsml", ".luaurc.rsml", "theme.luaurc.rsml"] {
+            fs::write(input.join(name), "").unwrap();
+            context.handle_vfs_event(VfsEvent::Create(input.join(name)));
+        }
+
+        assert!(input.join("luaurc.model.json").exists());
+        assert!(input.join(".luaurc.model.json").exists());
+        assert!(input.join("theme.luaurc.model.json").exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn malformed_luaurc_keeps_last_valid_configuration() {
+        let temp = std::env::temp_dir().join("rsml_test_malformed_luaurc");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("themes")).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        let luaurc_path = input.join(".luaurc");
+
+        fs::write(
+            &luaurc_path,
+            "{ \"aliases\": { \"theme\": \"./themes\", }, }",
+        )
+        .unwrap();
+        fs::write(input.join("button.rsml"), "@derive \"@theme/dark\";\n").unwrap();
+        fs::write(input.join("themes/dark.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        let dark_path = input.join("themes/dark.rsml");
+        let depends_on_dark = |context: &WatcherContext| {
+            context
+                .dependencies
+                .get_by_left(&input.join("button.rsml"))
+                .is_some_and(|dependencies| {
+                    dependencies
+                        .iter()
+                        .any(|path| path.to_path_buf() == dark_path)
+                })
+        };
+        assert!(depends_on_dark(&context));
+
+        fs::write(&luaurc_path, "{ \"aliases\": { \"theme\" \"./other\" } }").unwrap();
+        context.handle_vfs_event(VfsEvent::Write(luaurc_path.clone()));
+
+        let effective = context.luaurcs.effective(&input);
+        assert_eq!(effective.aliases.get("theme"), Some(&input.join("themes")));
+
+        context.handle_vfs_event(VfsEvent::Write(input.join("button.rsml")));
+        assert!(depends_on_dark(&context));
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn strict_mode_errors_block_output_until_luaurc_is_nonstrict() {
+        let temp = std::env::temp_dir().join("rsml_test_strict_mode");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        let luaurc_path = input.join(".luaurc");
+
+        fs::write(&luaurc_path, "{ \"languageMode\": \"strict\" }").unwrap();
+        fs::write(input.join("button.rsml"), "@derive \"./missing\";\n").unwrap();
+        fs::write(input.join("label.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        assert!(!input.join("button.model.json").exists());
+        assert!(input.join("label.model.json").exists());
+        assert_eq!(
+            context.failed.iter().collect::<Vec<_>>(),
+            vec![&input.join("button.rsml")]
+        );
+
+        fs::write(&luaurc_path, "{ \"languageMode\": \"nonstrict\" }").unwrap();
+        context.handle_vfs_event(VfsEvent::Write(luaurc_path.clone()));
+
+        assert!(input.join("button.model.json").exists());
+        assert!(context.failed.is_empty());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn language_mode_prefers_command_line_then_directive_then_luaurc() {
+        let temp = std::env::temp_dir().join("rsml_test_language_mode_precedence");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let input = dunce::canonicalize(&temp).unwrap();
+        fs::write(input.join(".luaurc"), "{ \"languageMode\": \"strict\" }").unwrap();
+        fs::write(input.join("luaurc.rsml"), "--!optimize 2\n").unwrap();
+        fs::write(
+            input.join("directive.rsml"),
+            "--!nonstrict\n--!optimize 2\n",
+        )
+        .unwrap();
+
+        let build = |language_mode| {
+            let mut context = WatcherContext::builder(&input)
+                .language_mode(language_mode)
+                .build()
+                .unwrap();
+            context.initialize();
+            context.failed
+        };
+
+        // Unknown directives are errors in strict mode, like problems with derives.
+        assert_eq!(
+            build(None).into_iter().collect::<Vec<_>>(),
+            vec![input.join("luaurc.rsml")]
+        );
+        assert_eq!(build(Some(LanguageMode::Nonstrict)).len(), 0);
+        assert_eq!(build(Some(LanguageMode::Strict)).len(), 2);
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn nested_luaurc_overrides_and_only_invalidates_its_directory() {
+        let temp = std::env::temp_dir().join("rsml_test_nested_luaurc");
+        let input = temp.join("src");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(input.join("special")).unwrap();
+        fs::create_dir_all(input.join("themes/dark")).unwrap();
+        fs::create_dir_all(input.join("themes/light")).unwrap();
+
+        let input = dunce::canonicalize(&input).unwrap();
+        let nested_luaurc_path = input.join("special/.luaurc");
+        let write_nested_luaurc = |theme: &str| {
+            let luaurc = format!(
+                "{{ \"aliases\": {{ \"theme\": \"../themes/{}\" }} }}",
+                theme
+            );
+            fs::write(&nested_luaurc_path, luaurc).unwrap();
+        };
+
+        fs::write(
+            input.join(".luaurc"),
+            "{ \"aliases\": { \"theme\": \"./themes/dark\" } }",
+        )
+        .unwrap();
+        write_nested_luaurc("light");
+
+        fs::write(input.join("button.rsml"), "@derive \"@theme/colors\";\n").unwrap();
+        fs::write(
+            input.join("special/panel.rsml"),
+            "@derive \"@theme/colors\";\n",
+        )
+        .unwrap();
+        fs::write(input.join("themes/dark/colors.rsml"), "").unwrap();
+        fs::write(input.join("themes/light/colors.rsml"), "").unwrap();
+
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, &input, &input, None).unwrap();
+        context.initialize();
+
+        let derive_target = |file: &str| {
+            let content: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(input.join(file)).unwrap()).unwrap();
+            content["children"][0]["attributes"]["Rojo_Target_StyleSheet"].clone()
+        };
+
+        let dark_id = "themes/dark/colors.rsml";
+        let light_id = "themes/light/colors.rsml";
+        assert_eq!(derive_target("button.model.json"), dark_id);
+        assert_eq!(derive_target("special/panel.model.json"), light_id);
+
+        // Files outside of the nested Luaurc's directory shouldn't be rebuilt.
+        fs::write(input.join("button.model.json"), "{}").unwrap();
+
+        write_nested_luaurc("dark");
+        context.handle_vfs_event(VfsEvent::Write(nested_luaurc_path.clone()));
+
+        assert_eq!(derive_target("special/panel.model.json"), dark_id);
+        assert_eq!(
+            fs::read_to_string(input.join("button.model.json")).unwrap(),
+            "{}"
+        );
+
+        // Removing the nested Luaurc falls back to the parent Luaurc.
+        write_nested_luaurc("light");
+        context.handle_vfs_event(VfsEvent::Write(nested_luaurc_path.clone()));
+        fs::remove_file(&nested_luaurc_path).unwrap();
+        context.handle_vfs_event(VfsEvent::Remove(nested_luaurc_path.clone()));
+
+        assert_eq!(derive_target("special/panel.model.json"), dark_id);
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use memofs::Vfs;
+use rbx_rsml::types::LanguageMode;
+
+use crate::{Diagnostic, FindFirstChild, NormalizePath, guarded_unwrap};
+
+use super::{Dependants, Luaurc, LuaurcError, copy_language_mode, same_language_mode};
+
+pub fn is_config_luau_path(path: &Path) -> bool {
+    path.file_name() == Some(OsStr::new(".config.luau"))
+}
+
+pub fn is_luaurc_path(path: &Path) -> bool {
+    path.file_name() == Some(OsStr::new(".luaurc")) || is_config_luau_path(path)
+}
+
+/// Finds the configuration file in the directory, where a
+/// `.config.luau` takes precedence over a Luaurc next to it.
+pub fn scan_for_luaurc(origin_dir: &Path, diagnostics: &mut Vec<Diagnostic>) -> Option<PathBuf> {
+    let config_luau_path = origin_dir.join(".config.luau");
+    let luaurc_path = origin_dir.find_first_child(|path| {
+        path.is_file() && is_luaurc_path(path) && !is_config_luau_path(path)
+    });
+
+    if !config_luau_path.is_file() {
+        return luaurc_path;
+    }
+
+    if let Some(luaurc_path) = luaurc_path {
+        diagnostics.push(Diagnostic::warning(format!(
+            "Ignoring {:?} as {:?} takes precedence over it.",
+            luaurc_path, config_luau_path
+        )));
+    }
+
+    Some(config_luau_path)
+}
+
+/// The configuration which applies to a directory, merged from
+/// every Luaurc in its ancestors with nearer files taking precedence.
+#[derive(Debug, Default)]
+pub struct EffectiveLuaurc {
+    /// Alias names mapped to the paths they resolve to.
+    pub aliases: BTreeMap<String, PathBuf>,
+    pub language_mode: Option<LanguageMode>,
+}
+
+impl EffectiveLuaurc {
+    fn apply(&mut self, luaurc_path: &Path, luaurc: &Luaurc) {
+        let luaurc_dir = luaurc_path.parent().unwrap_or(luaurc_path);
+
+        // Aliases are relative to the directory of the Luaurc which defines them.
+        for (name, value) in luaurc.aliases.iter() {
+            self.aliases
+                .insert(name.clone(), luaurc_dir.join(value).normalize());
+        }
+
+        if let Some(language_mode) = &luaurc.language_mode {
+            self.language_mode = Some(copy_language_mode(language_mode));
+        }
+    }
+}
+
+/// What changed after a Luaurc was reloaded.
+pub struct LuaurcChange {
+    /// The directory whose descendants are affected, or `None` if every file is.
+    pub dir: Option<PathBuf>,
+    pub aliases: Vec<String>,
+    pub language_mode: bool,
+}
+
+/// Discovers and caches the Luaurc files which apply to each directory.
+pub struct LuaurcChain {
+    vfs: Arc<Vfs>,
+
+    /// The user specified Luaurc, which applies beneath every discovered Luaurc.
+    pub base: Option<(PathBuf, Luaurc)>,
+
+    /// Directories mapped to the Luaurc inside of them, if they have one.
+    discovered: BTreeMap<PathBuf, Option<(PathBuf, Luaurc)>>,
+
+    /// Alias names mapped to the files which derive through them.
+    pub dependants: Dependants,
+
+    /// Problems found while reading Luaurcs, which haven't been reported yet.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LuaurcChain {
+    pub fn new(vfs: Arc<Vfs>, base_path: Option<&PathBuf>) -> Self {
+        let mut diagnostics = Vec::new();
+        let base = base_path.map(|base_path| {
+            let luaurc = load_luaurc(&vfs, base_path, &mut diagnostics);
+            (base_path.clone(), luaurc)
+        });
+
+        Self {
+            vfs,
+            base,
+            discovered: BTreeMap::new(),
+            dependants: Dependants::new(),
+            diagnostics,
+        }
+    }
+
+    /// Discovers the Luaurc files in the directory and all of its ancestors.
+    pub fn discover(&mut self, dir: &Path) {
+        for ancestor in dir.ancestors() {
+            if self.discovered.contains_key(ancestor) {
+                continue;
+            }
+
+            // The user specified Luaurc is already applied beneath every other Luaurc.
+            let luaurc_path = scan_for_luaurc(ancestor, &mut self.diagnostics)
+                .filter(|luaurc_path| !self.is_base(luaurc_path));
+            let luaurc = luaurc_path.map(|luaurc_path| {
+                let luaurc = load_luaurc(&self.vfs, &luaurc_path, &mut self.diagnostics);
+                (luaurc_path, luaurc)
+            });
+
+            self.discovered.insert(ancestor.to_path_buf(), luaurc);
+        }
+    }
+
+    /// Whether the path is the Luaurc specified with the `--luaurc` flag.
+    pub fn is_base(&self, luaurc_path: &Path) -> bool {
+        matches!(&self.base, Some((base_path, _)) if base_path == luaurc_path)
+    }
+
+    /// Every Luaurc which has been discovered so far.
+    pub fn discovered_paths(&self) -> Vec<&PathBuf> {
+        self.discovered
+            .values()
+            .filter_map(|luaurc| luaurc.as_ref().map(|(luaurc_path, _)| luaurc_path))
+            .collect()
+    }
+
+    /// The configuration for files in the specified directory.
+    pub fn effective(&mut self, dir: &Path) -> EffectiveLuaurc {
+        self.discover(dir);
+
+        let mut effective = EffectiveLuaurc::default();
+
+        if let Some((base_path, base)) = &self.base {
+            effective.apply(base_path, base);
+        }
+
+        let chain = dir
+            .ancestors()
+            .filter_map(|ancestor| self.discovered.get(ancestor)?.as_ref())
+            .collect::<Vec<_>>();
+
+        // Applies the farthest Luaurc first so nearer ones override it.
+        for (luaurc_path, luaurc) in chain.into_iter().rev() {
+            effective.apply(luaurc_path, luaurc);
+        }
+
+        effective
+    }
+
+    /// Rereads the Luaurc at the specified path (or its removal), returning what changed.
+    ///
+    /// A Luaurc which fails to parse is reported and its last valid configuration is kept.
+    pub fn reload(&mut self, luaurc_path: &Path) -> Option<LuaurcChange> {
+        if let Some((base_path, base)) = &mut self.base
+            && base_path == luaurc_path
+        {
+            let fresh = match read_luaurc(&self.vfs, luaurc_path)? {
+                Ok(fresh) => fresh,
+                Err(error) => {
+                    self.diagnostics
+                        .push(kept_luaurc_diagnostic(luaurc_path, &error));
+                    return None;
+                }
+            };
+            let change = LuaurcChange::between(None, base, &fresh);
+            *base = fresh;
+
+            return Some(change);
+        }
+
+        let dir = luaurc_path.parent()?;
+
+        // The directory may hold a different Luaurc now that this one has changed.
+        let fresh_path = scan_for_luaurc(dir, &mut self.diagnostics)
+            .filter(|fresh_path| !self.is_base(fresh_path));
+        let fresh = match fresh_path {
+            Some(fresh_path) => match read_luaurc(&self.vfs, &fresh_path) {
+                Some(Ok(fresh)) => Some((fresh_path, fresh)),
+                Some(Err(error)) => {
+                    self.diagnostics
+                        .push(kept_luaurc_diagnostic(&fresh_path, &error));
+                    return None;
+                }
+                None => None,
+            },
+            None => None,
+        };
+
+        let empty = Luaurc::default();
+        let old = self
+            .discovered
+            .get(dir)
+            .and_then(|old| old.as_ref())
+            .map(|(_, old)| old)
+            .unwrap_or(&empty);
+        let change = LuaurcChange::between(
+            Some(dir.to_path_buf()),
+            old,
+            fresh.as_ref().map(|(_, fresh)| fresh).unwrap_or(&empty),
+        );
+
+        self.discovered.insert(dir.to_path_buf(), fresh);
+
+        Some(change)
+    }
+
+    /// The files which need rebuilding after the specified change.
+    pub fn affected_by(&self, change: &LuaurcChange) -> BTreeSet<PathBuf> {
+        let mut affected = BTreeSet::new();
+
+        for alias in &change.aliases {
+            let dependants = guarded_unwrap!(self.dependants.get_by_left(alias), continue);
+
+            affected.extend(
+                dependants
+                    .iter()
+                    .filter(|dependant| match &change.dir {
+                        Some(dir) => dependant.starts_with(dir),
+                        None => true,
+                    })
+                    .map(|dependant| dependant.to_path_buf()),
+            );
+        }
+
+        affected
+    }
+}
+
+impl LuaurcChange {
+    fn between(dir: Option<PathBuf>, old: &Luaurc, fresh: &Luaurc) -> Self {
+        Self {
+            dir,
+            aliases: fresh.aliases.diff(&old.aliases).cloned().collect(),
+            language_mode: !same_language_mode(
+                fresh.language_mode.as_ref(),
+                old.language_mode.as_ref(),
+            ),
+        }
+    }
+}
+
+/// Reads the Luaurc at the specified path, returning `None` if the file couldn't be read.
+fn read_luaurc(vfs: &Vfs, luaurc_path: &Path) -> Option<Result<Luaurc, LuaurcError>> {
+    // Reading through the vfs means the Luaurc is watched for changes.
+    let contents = vfs.read(luaurc_path).ok()?;
+    let contents = String::from_utf8_lossy(&contents);
+
+    Some(match is_config_luau_path(luaurc_path) {
+        true => Luaurc::from_config_luau(contents),
+        false => Luaurc::new(contents),
+    })
+}
+
+/// Reads the Luaurc at the specified path, reporting any errors and using an empty Luaurc instead.
+fn load_luaurc(vfs: &Vfs, luaurc_path: &Path, diagnostics: &mut Vec<Diagnostic>) -> Luaurc {
+    match read_luaurc(vfs, luaurc_path) {
+        Some(Ok(luaurc)) => luaurc,
+
+        Some(Err(error)) => {
+            diagnostics.push(Diagnostic::error(format!(
+                "Could not parse Luaurc at {:#?}: {}. It will be ignored until it is fixed.",
+                luaurc_path, error
+            )));
+            Luaurc::default()
+        }
+
+        None => Luaurc::default(),
+    }
+}
+
+fn kept_luaurc_diagnostic(luaurc_path: &Path, error: &LuaurcError) -> Diagnostic {
+    Diagnostic::error(format!(
+        "Could not parse Luaurc at {:#?}: {}. The last valid configuration will be used until it is fixed.",
+        luaurc_path, error
+    ))
+}
@@ -0,0 +1,381 @@
+use serde_json::{Map, Number, Value};
+
+use super::LuaurcError;
+
+/// Reads the table returned by a `.config.luau` file into json.
+///
+/// Only the static subset of Luau is supported: a single `return` of a table
+/// literal made up of strings, numbers, booleans, `nil` and nested tables.
+pub fn parse(source: &str) -> Result<Value, LuaurcError> {
+    let mut parser = Parser { source, pos: 0 };
+
+    parser.skip_trivia()?;
+    parser.expect_keyword("return")?;
+    parser.skip_trivia()?;
+
+    let value = parser.parse_value()?;
+
+    parser.skip_trivia()?;
+    parser.eat(';');
+    parser.skip_trivia()?;
+
+    if parser.pos < source.len() {
+        return Err(parser.error("expected the end of the file after the returned table"));
+    }
+
+    Ok(value.unwrap_or(Value::Null))
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> LuaurcError {
+        let consumed = &self.source[..self.pos];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed
+            .rsplit('\n')
+            .next()
+            .map(|line| line.chars().count())
+            .unwrap_or(0)
+            + 1;
+
+        LuaurcError::new(message, Some((line, column)))
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let char = self.peek()?;
+        self.pos += char.len_utf8();
+        Some(char)
+    }
+
+    fn guarded_bump(&mut self) -> Result<char, LuaurcError> {
+        self.bump()
+            .ok_or_else(|| self.error("unexpected end of file"))
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), LuaurcError> {
+        match self.eat(expected) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{}`", expected))),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), LuaurcError> {
+        match self.parse_name() {
+            Some(name) if name == keyword => Ok(()),
+            _ => Err(self.error(format!("expected `{}`", keyword))),
+        }
+    }
+
+    // Skips whitespace and comments.
+    fn skip_trivia(&mut self) -> Result<(), LuaurcError> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if !self.rest().starts_with("--") {
+                return Ok(());
+            }
+            self.pos += 2;
+
+            if self.long_bracket_level().is_some() {
+                self.parse_long_bracket("comment")?;
+            } else {
+                let line_len = self.rest().find('\n').unwrap_or(self.rest().len());
+                self.pos += line_len;
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Option<&str> {
+        let start = self.pos;
+        let rest = self.rest();
+
+        let len = rest
+            .char_indices()
+            .find(|(idx, char)| match idx {
+                0 => !(char.is_ascii_alphabetic() || *char == '_'),
+                _ => !(char.is_ascii_alphanumeric() || *char == '_'),
+            })
+            .map(|(idx, _)| idx)
+            .unwrap_or(rest.len());
+
+        if len == 0 {
+            return None;
+        }
+
+        self.pos += len;
+        Some(&self.source[start..self.pos])
+    }
+
+    // Values of `nil` are returned as `None`.
+    fn parse_value(&mut self) -> Result<Option<Value>, LuaurcError> {
+        match self.peek() {
+            Some('{') => self.parse_table().map(Some),
+            Some('"' | '\'') => self.parse_quoted_string().map(|x| Some(Value::String(x))),
+            Some('[') if self.long_bracket_level().is_some() => self
+                .parse_long_bracket("string")
+                .map(|x| Some(Value::String(x))),
+            Some('-' | '.' | '0'..='9') => self.parse_number().map(Some),
+
+            Some(char) if char.is_ascii_alphabetic() || char == '_' => {
+                let start = self.pos;
+
+                match self.parse_name() {
+                    Some("true") => Ok(Some(Value::Bool(true))),
+                    Some("false") => Ok(Some(Value::Bool(false))),
+                    Some("nil") => Ok(None),
+                    _ => {
+                        self.pos = start;
+                        Err(self
+                            .error("only tables, strings, numbers, booleans and nil are supported"))
+                    }
+                }
+            }
+
+            Some(_) => {
+                Err(self.error("only tables, strings, numbers, booleans and nil are supported"))
+            }
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn parse_table(&mut self) -> Result<Value, LuaurcError> {
+        self.expect('{')?;
+
+        let mut map = Map::new();
+        let mut array = Vec::new();
+
+        loop {
+            self.skip_trivia()?;
+
+            if self.eat('}') {
+                break;
+            }
+
+            let field_start = self.pos;
+
+            let key = if self.peek() == Some('[') && self.long_bracket_level().is_none() {
+                self.bump();
+                self.skip_trivia()?;
+
+                let key = match self.parse_value()? {
+                    Some(Value::String(key)) => key,
+                    Some(Value::Number(key)) => key.to_string(),
+                    _ => return Err(self.error("table keys must be strings or numbers")),
+                };
+
+                self.skip_trivia()?;
+                self.expect(']')?;
+                self.skip_trivia()?;
+                self.expect('=')?;
+
+                Some(key)
+            } else if let Some(name) = self.parse_name().map(|name| name.to_string()) {
+                self.skip_trivia()?;
+
+                if self.eat('=') {
+                    Some(name)
+                } else {
+                    // Not a named field, so it's reparsed as a positional value.
+                    self.pos = field_start;
+                    None
+                }
+            } else {
+                None
+            };
+
+            self.skip_trivia()?;
+            let value = self.parse_value()?;
+
+            match (key, value) {
+                (Some(key), Some(value)) => {
+                    map.insert(key, value);
+                }
+                (Some(_), None) => (),
+                (None, value) => array.push(value.unwrap_or(Value::Null)),
+            }
+
+            self.skip_trivia()?;
+
+            if !(self.eat(',') || self.eat(';')) {
+                self.skip_trivia()?;
+                self.expect('}')?;
+                break;
+            }
+        }
+
+        // Empty tables are objects since configuration fields are never arrays.
+        if map.is_empty() && !array.is_empty() {
+            return Ok(Value::Array(array));
+        }
+
+        // Positional values of mixed tables are keyed by their index.
+        for (idx, value) in array.into_iter().enumerate() {
+            map.insert((idx + 1).to_string(), value);
+        }
+
+        Ok(Value::Object(map))
+    }
+
+    fn parse_quoted_string(&mut self) -> Result<String, LuaurcError> {
+        let quote = self.guarded_bump()?;
+        let mut string = String::new();
+
+        loop {
+            match self.bump() {
+                Some(char) if char == quote => return Ok(string),
+                Some('\n') | None => return Err(self.error("unfinished string")),
+
+                Some('\\') => match self.bump() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('0') => string.push('\0'),
+                    Some('\n') => string.push('\n'),
+                    Some('z') => {
+                        let rest = self.rest();
+                        self.pos += rest.len() - rest.trim_start().len();
+                    }
+                    Some(char @ ('\\' | '"' | '\'')) => string.push(char),
+                    _ => return Err(self.error("unsupported escape sequence")),
+                },
+
+                Some(char) => string.push(char),
+            }
+        }
+    }
+
+    // The level of the long bracket at the current position, such as 2 for `[==[`.
+    fn long_bracket_level(&self) -> Option<usize> {
+        let rest = self.rest().strip_prefix('[')?;
+        let level = rest.chars().take_while(|char| *char == '=').count();
+
+        rest[level..].starts_with('[').then_some(level)
+    }
+
+    fn guarded_long_bracket_level(&self) -> Result<usize, LuaurcError> {
+        self.long_bracket_level()
+            .ok_or_else(|| self.error("expected a long bracket"))
+    }
+
+    fn parse_long_bracket(&mut self, kind: &str) -> Result<String, LuaurcError> {
+        let level = self.guarded_long_bracket_level()?;
+        self.pos += level + 2;
+
+        let closing = format!("]{}]", "=".repeat(level));
+        let end = match self.rest().find(&closing) {
+            Some(end) => end,
+            None => return Err(self.error(format!("unfinished long {}", kind))),
+        };
+
+        // A newline directly after the opening bracket is skipped.
+        let content = self.rest()[..end].to_string();
+        let content = content
+            .strip_prefix("\r\n")
+            .or_else(|| content.strip_prefix('\n'))
+            .unwrap_or(&content)
+            .to_string();
+
+        self.pos += end + closing.len();
+
+        Ok(content)
+    }
+
+    fn parse_number(&mut self) -> Result<Value, LuaurcError> {
+        let start = self.pos;
+        let negative = self.eat('-');
+        let digits_start = self.pos;
+
+        let is_hex = self.rest().starts_with("0x") || self.rest().starts_with("0X");
+        let mut previous = None;
+
+        // Exponents may be signed, such as `1e-5`, which hex digits never are.
+        while let Some(char) = self.peek() {
+            let is_exponent_sign =
+                matches!(char, '+' | '-') && matches!(previous, Some('e' | 'E')) && !is_hex;
+
+            if !(char.is_ascii_alphanumeric() || char == '.' || char == '_' || is_exponent_sign) {
+                break;
+            }
+
+            self.bump();
+            previous = Some(char);
+        }
+
+        let digits = self.source[digits_start..self.pos].replace('_', "");
+        let hex = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"));
+
+        let number = match hex {
+            Some(hex) => i64::from_str_radix(hex, 16).ok().map(Number::from),
+            None => match digits.parse::<i64>() {
+                Ok(integer) => Some(Number::from(integer)),
+                Err(_) => digits.parse::<f64>().ok().and_then(Number::from_f64),
+            },
+        };
+
+        match number {
+            Some(number) if negative => Ok(match number.as_i64() {
+                Some(integer) => Value::Number(Number::from(-integer)),
+                None => Value::Number(Number::from_f64(-number.as_f64().unwrap()).unwrap()),
+            }),
+            Some(number) => Ok(Value::Number(number)),
+            None => {
+                self.pos = start;
+                Err(self.error("malformed number"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_static_tables() {
+        let value = parse(
+            "--[[ Comment ]]\nreturn {\n\tname = 'a\\tb',\n\t[\"key\"] = [==[\nlong]==];\n\tlist = { 1, -2.5, 0x10, 1e-5, 2E+3, true, nil },\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "name": "a\tb",
+                "key": "long",
+                "list": [1, -2.5, 16, 0.00001, 2000.0, true, null],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_dynamic_expressions() {
+        let error = parse("return {\n\taliases = getAliases(),\n}").unwrap_err();
+
+        assert_eq!(error.position, Some((2, 12)));
+    }
+}
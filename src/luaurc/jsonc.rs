@@ -0,0 +1,104 @@
+/// Blanks out the comments and trailing commas which Luau allows in a Luaurc,
+/// so it can be read as plain json. The `//` and `/* */` comments Luau rejects
+/// are also blanked out, as an RSML extension.
+///
+/// Removed characters are replaced with spaces (newlines are kept) so the
+/// line and column of any later error still point into the original file.
+pub fn to_json(contents: &str) -> String {
+    let mut bytes = contents.as_bytes().to_vec();
+    let len = bytes.len();
+
+    // The comma which would be trailing if the next token closes an object or array.
+    let mut pending_comma: Option<usize> = None;
+    let mut idx = 0;
+
+    while idx < len {
+        match (bytes[idx], bytes.get(idx + 1)) {
+            (b'"', _) => {
+                pending_comma = None;
+                idx += 1;
+
+                while idx < len {
+                    match bytes[idx] {
+                        b'\\' => idx += 2,
+                        b'"' => break,
+                        _ => idx += 1,
+                    }
+                }
+            }
+
+            (b'-', Some(b'-')) => {
+                let end = luau_comment_end(&bytes, idx);
+                idx = blank(&mut bytes, idx, end);
+                continue;
+            }
+
+            (b'/', Some(b'/')) => {
+                let end = find(&bytes, idx, b"\n");
+                idx = blank(&mut bytes, idx, end.unwrap_or(len));
+                continue;
+            }
+
+            (b'/', Some(b'*')) => {
+                let end = find(&bytes, idx + 2, b"*/").map(|end| end + 2);
+                idx = blank(&mut bytes, idx, end.unwrap_or(len));
+                continue;
+            }
+
+            (b',', _) => pending_comma = Some(idx),
+
+            (b'}' | b']', _) => {
+                if let Some(comma) = pending_comma.take() {
+                    bytes[comma] = b' ';
+                }
+            }
+
+            (byte, _) if byte.is_ascii_whitespace() => (),
+
+            _ => pending_comma = None,
+        }
+
+        idx += 1;
+    }
+
+    // Only whole comments were replaced, so the contents are still valid utf-8.
+    String::from_utf8(bytes).unwrap_or_else(|_| contents.to_string())
+}
+
+/// The end of the Luau comment starting at `idx`, which is after the closing
+/// bracket of a long comment, or the end of the line for other comments.
+pub fn luau_comment_end(bytes: &[u8], idx: usize) -> usize {
+    let end = match long_bracket_level(&bytes[idx + 2..]) {
+        Some(level) => {
+            let closing = [b"]".as_slice(), &b"=".repeat(level), b"]"].concat();
+            find(bytes, idx, &closing).map(|end| end + closing.len())
+        }
+        None => find(bytes, idx, b"\n"),
+    };
+
+    end.unwrap_or(bytes.len())
+}
+
+fn long_bracket_level(bytes: &[u8]) -> Option<usize> {
+    let rest = bytes.strip_prefix(b"[")?;
+    let level = rest.iter().take_while(|byte| **byte == b'=').count();
+
+    (rest.get(level) == Some(&b'[')).then_some(level)
+}
+
+fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+fn blank(bytes: &mut [u8], from: usize, to: usize) -> usize {
+    for byte in &mut bytes[from..to] {
+        if *byte != b'\n' {
+            *byte = b' ';
+        }
+    }
+
+    to
+}
@@ -1,6 +1,7 @@
 use serde::de::Deserialize;
 use std::{
     collections::BTreeMap,
+    fmt,
     ops::{Deref, DerefMut},
     path::PathBuf,
 };
@@ -9,6 +10,59 @@ use rbx_rsml::types::LanguageMode;
 
 use crate::multibimap::MultiBiMap;
 
+mod chain;
+pub use chain::{LuaurcChain, is_luaurc_path};
+
+mod config_luau;
+mod jsonc;
+pub(crate) use jsonc::luau_comment_end;
+
+/// Why a Luaurc or `.config.luau` file couldn't be read.
+#[derive(Debug)]
+pub struct LuaurcError {
+    pub message: String,
+
+    /// The line and column of the error, if it was caused by the syntax of the file.
+    pub position: Option<(usize, usize)>,
+}
+
+impl LuaurcError {
+    pub fn new(message: impl Into<String>, position: Option<(usize, usize)>) -> Self {
+        Self {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+impl From<serde_json::Error> for LuaurcError {
+    fn from(error: serde_json::Error) -> Self {
+        let message = error.to_string();
+
+        // Serde appends the position to the message when it knows it.
+        match error.line() {
+            0 => Self::new(message, None),
+            line => {
+                let suffix = format!(" at line {} column {}", line, error.column());
+                let message = message.strip_suffix(&suffix).unwrap_or(&message);
+
+                Self::new(message, Some((line, error.column())))
+            }
+        }
+    }
+}
+
+impl fmt::Display for LuaurcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => {
+                write!(f, "{} at line {} column {}", self.message, line, column)
+            }
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Aliases(pub BTreeMap<String, String>);
 
@@ -27,10 +81,6 @@ impl DerefMut for Aliases {
 }
 
 impl Aliases {
-    pub fn new<S: AsRef<str>>(contents: S) -> Self {
-        Luaurc::new(contents).aliases
-    }
-
     pub fn diff<'a>(&'a self, b: &'a Aliases) -> impl Iterator<Item = &'a String> {
         let mut ia = self.iter();
         let mut ib = b.iter();
@@ -105,8 +155,63 @@ impl Dependants {
 #[derive(Default, Debug)]
 pub struct Luaurc {
     pub aliases: Aliases,
-    pub dependants: Dependants,
-    pub language_mode: LanguageMode,
+
+    /// `None` when the Luaurc doesn't specify a language mode.
+    pub language_mode: Option<LanguageMode>,
+}
+
+// Only matches on the variants of `LanguageMode` so no other traits are relied upon.
+// The matches are exhaustive so a new variant has to be handled here rather than being collapsed.
+pub fn copy_language_mode(language_mode: &LanguageMode) -> LanguageMode {
+    match language_mode {
+        LanguageMode::Strict => LanguageMode::Strict,
+        LanguageMode::Nonstrict => LanguageMode::Nonstrict,
+    }
+}
+
+pub fn same_language_mode(a: Option<&LanguageMode>, b: Option<&LanguageMode>) -> bool {
+    matches!(
+        (a, b),
+        (Some(LanguageMode::Strict), Some(LanguageMode::Strict))
+            | (Some(LanguageMode::Nonstrict), Some(LanguageMode::Nonstrict))
+            | (None, None)
+    )
+}
+
+/// A `languageMode` value, which is an error if it isn't a mode Luau knows about.
+struct LanguageModeValue(LanguageMode);
+
+impl<'de> Deserialize<'de> for LanguageModeValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{Error, Unexpected, Visitor};
+
+        struct LanguageModeVisitor;
+
+        impl Visitor<'_> for LanguageModeVisitor {
+            type Value = LanguageModeValue;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a languageMode of \"strict\", \"nonstrict\" or \"nocheck\"")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: Error,
+            {
+                // There's no separate mode for `nocheck`, so it's the same as `nonstrict`.
+                match value {
+                    "strict" => Ok(LanguageModeValue(LanguageMode::Strict)),
+                    "nonstrict" | "nocheck" => Ok(LanguageModeValue(LanguageMode::Nonstrict)),
+                    _ => Err(E::invalid_value(Unexpected::Str(value), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_str(LanguageModeVisitor)
+    }
 }
 
 impl<'de> Deserialize<'de> for Luaurc {
@@ -131,20 +236,23 @@ impl<'de> Deserialize<'de> for Luaurc {
                 M: MapAccess<'de>,
             {
                 let mut aliases = Aliases::default();
-                let mut language_mode = LanguageMode::default();
+                let mut language_mode = None;
 
                 while let Some(key) = access.next_key::<String>()? {
                     match key.as_str() {
                         "aliases" => {
                             let map: BTreeMap<String, String> = access.next_value()?;
-                            aliases = Aliases(map);
+
+                            // Alias names are case insensitive.
+                            aliases = Aliases(
+                                map.into_iter()
+                                    .map(|(name, value)| (name.to_lowercase(), value))
+                                    .collect(),
+                            );
                         }
                         "languageMode" => {
-                            let value: serde_json::Value = access.next_value()?;
-                            language_mode = match value.as_str() {
-                                Some("strict") => LanguageMode::Strict,
-                                _ => LanguageMode::Nonstrict,
-                            };
+                            let LanguageModeValue(value) = access.next_value()?;
+                            language_mode = Some(value);
                         }
                         _ => {
                             let _: serde::de::IgnoredAny = access.next_value()?;
@@ -154,7 +262,6 @@ impl<'de> Deserialize<'de> for Luaurc {
 
                 Ok(Luaurc {
                     aliases,
-                    dependants: Dependants::new(),
                     language_mode,
                 })
             }
@@ -165,7 +272,89 @@ impl<'de> Deserialize<'de> for Luaurc {
 }
 
 impl Luaurc {
-    pub fn new<S: AsRef<str>>(contents: S) -> Self {
-        serde_json::from_str::<Luaurc>(contents.as_ref()).unwrap_or_else(|_| Luaurc::default())
+    /// Reads a Luaurc, which is json that also allows comments and trailing commas.
+    pub fn new<S: AsRef<str>>(contents: S) -> Result<Self, LuaurcError> {
+        Ok(serde_json::from_str::<Luaurc>(&jsonc::to_json(
+            contents.as_ref(),
+        ))?)
+    }
+
+    /// Reads the configuration from the table returned by a `.config.luau` file,
+    /// which may be nested inside of a `luau` field.
+    pub fn from_config_luau<S: AsRef<str>>(contents: S) -> Result<Self, LuaurcError> {
+        let mut value = config_luau::parse(contents.as_ref())?;
+
+        if let Some(luau) = value.get_mut("luau") {
+            value = luau.take();
+        }
+
+        // `.config.luau` files spell it in lowercase.
+        if let Some(config) = value.as_object_mut()
+            && let Some(language_mode) = config.remove("languagemode")
+        {
+            config.insert("languageMode".to_string(), language_mode);
+        }
+
+        Ok(serde_json::from_value::<Luaurc>(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_comments_and_trailing_commas() {
+        let luaurc = Luaurc::new(
+            "{\n\t-- Luau comment\n\t\"languageMode\": \"strict\", // line comment\n\t/* block */ \"aliases\": {\n\t\t--[[ long\n\t\tcomment ]] \"Themes\": \"./themes\",\n\t},\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(luaurc.aliases.get("themes"), Some(&"./themes".to_string()));
+        assert!(matches!(luaurc.language_mode, Some(LanguageMode::Strict)));
+    }
+
+    #[test]
+    fn reports_position_of_syntax_errors() {
+        let error =
+            Luaurc::new("{\n\t\"aliases\": {\n\t\t\"themes\" \"./themes\"\n\t}\n}").unwrap_err();
+
+        assert_eq!(error.position, Some((3, 12)));
+        assert_eq!(error.to_string(), "expected `:` at line 3 column 12");
+    }
+
+    #[test]
+    fn rejects_unknown_language_modes() {
+        let language_mode =
+            |contents: &str| Luaurc::new(contents).map(|luaurc| luaurc.language_mode);
+
+        assert!(matches!(
+            language_mode("{ \"languageMode\": \"nocheck\" }"),
+            Ok(Some(LanguageMode::Nonstrict))
+        ));
+
+        for value in ["\"stict\"", "1", "null"] {
+            let error =
+                language_mode(&format!("{{\n\t\"languageMode\": {}\n}}", value)).unwrap_err();
+            assert!(
+                error.message.ends_with(
+                    "expected a languageMode of \"strict\", \"nonstrict\" or \"nocheck\""
+                ),
+                "{}",
+                error
+            );
+            assert_eq!(error.position.map(|(line, _)| line), Some(2));
+        }
+
+        // Only `.config.luau` files spell it in lowercase.
+        assert!(matches!(
+            language_mode("{ \"languagemode\": \"strict\" }"),
+            Ok(None)
+        ));
+        assert!(matches!(
+            Luaurc::from_config_luau("return { luau = { languagemode = \"strict\" } }")
+                .map(|luaurc| luaurc.language_mode),
+            Ok(Some(LanguageMode::Strict))
+        ));
     }
 }
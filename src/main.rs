@@ -1,524 +1,284 @@
-mod normalize_path;
-pub use normalize_path::NormalizePath;
-
-mod rsml_to_model_json;
-use rsml_to_model_json::rsml_to_model_json;
-
-mod guarded_unwrap;
-
-use clap::{Parser, Subcommand, crate_version};
-use serde::Deserialize;
+use clap::{Args, Parser, Subcommand, ValueEnum, crate_version};
+use rsml_cli::{
+    LanguageMode, OutputEvent, OutputListener, ServeState, SessionStats, SymlinkPolicy, Watcher,
+    WatcherContext,
+};
 
 use std::{
-    collections::HashSet,
-    ffi::OsStr,
-    fs,
     io::{Write, stdout},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, mpsc},
     time::{Duration, Instant},
 };
 
-use crossbeam_channel::{RecvError, Sender, select};
-use jod_thread::JoinHandle;
-use memofs::{ReadDir, StdBackend, Vfs, VfsEvent};
-
-use crate::{guarded_unwrap::GuardedUnwrap, luaurc::Aliases, multibimap::MultiBiMap};
-
-pub mod luaurc;
-use luaurc::Luaurc;
+// Shared with the library, which keeps them private.
+#[path = "guarded_unwrap.rs"]
+mod guarded_unwrap;
+use guarded_unwrap::guarded_unwrap;
 
-pub mod multibimap;
-use multibimap::Ref;
+#[path = "normalize_path.rs"]
+mod normalize_path;
+use normalize_path::NormalizePath;
+
+/// Watches until the process is interrupted or the input directory is removed,
+/// then summarises the session.
+fn watch_until_stopped(context: WatcherContext, poll: Option<Duration>) {
+    let started = Instant::now();
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+
+    let interrupt_sender = stop_sender.clone();
+    if let Err(err) = ctrlc::set_handler(move || {
+        let _ = interrupt_sender.send(());
+    }) {
+        let _ = writeln!(
+            stdout(),
+            "WARNING: Could not listen for Ctrl+C, so the session won't be summarised: {}",
+            err
+        );
+    }
 
-#[derive(Deserialize)]
-pub struct ModelJsonId {
-    id: String,
-}
+    if let Some(interval) = poll {
+        let _ = writeln!(stdout(), "Polling for changes every {:?}.", interval);
+    }
+    let watcher = Watcher::start(context, stop_sender);
+    let _ = stop_receiver.recv();
 
-#[derive(Debug)]
-enum CreateFileDependencies<'a> {
-    True(Option<&'a Path>),
-    False,
+    let context = watcher.stop();
+    let _ = writeln!(
+        stdout(),
+        "{}",
+        session_summary(&context.stats, started.elapsed())
+    );
 }
 
-fn model_json_is_rsml(path: &Path) -> bool {
-    let contents = guarded_unwrap!(fs::read_to_string(path), return false);
-    let model: ModelJsonId = guarded_unwrap!(serde_json::from_str(&contents), return false);
-
-    model.id.ends_with(".rsml")
+fn session_summary(stats: &SessionStats, elapsed: Duration) -> String {
+    format!(
+        "RSML CLI stopped after {:.1?}. Rebuilt {} file(s) with {} error(s), spending {:.1?} compiling.",
+        elapsed, stats.rebuilds, stats.errors, stats.compile_time
+    )
 }
 
-pub struct WatcherContext {
-    pub vfs: Arc<Vfs>,
-    pub input_dir: PathBuf,
-    pub output_dir: PathBuf,
-    pub dependencies: MultiBiMap<PathBuf, PathBuf>,
-    pub luaurc: Option<(PathBuf, Luaurc)>,
+#[derive(Parser)]
+#[command(name = "CLI")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
 }
 
-impl WatcherContext {
-    fn handle_vfs_event(&mut self, event: VfsEvent) {
-        self.vfs
-            .commit_event(&event)
-            .expect("Error applying VFS change");
-
-        let path = match &event {
-            VfsEvent::Create(path) | VfsEvent::Write(path) | VfsEvent::Remove(path) => {
-                dunce::canonicalize(path).unwrap_or_else(|_| path.normalize())
-            }
-
-            _ => return,
-        };
-
-        if let Some(file_name) = path.file_name()
-            && file_name.to_string_lossy().ends_with(".model.json")
-        {
-            return;
-        }
-
-        let is_rsml_ext = path.extension() == Some(OsStr::new("rsml"));
-
-        if path.is_file() {
-            if is_rsml_ext {
-                self.dependencies.remove_by_left(path.clone());
-                self.create_file(&path, CreateFileDependencies::True(None));
-
-            // We have found our luaurc file.
-            } else if let Some((luaurc_path, _)) = &self.luaurc
-                && &path == luaurc_path
-            {
-                self.luaurc_update(luaurc_path.clone());
-            }
-        } else if path.is_dir() {
-            self.recursive_scan(&path);
-
-        // path no longer exists, remove it (the Remove event can't be relied upon).
-        } else {
-            if is_rsml_ext {
-                let _ = fs::remove_file(&path);
-
-                self.dependencies.remove_by_left(path.clone());
-
-                if let Some((_, luaurc)) = self.luaurc.as_mut() {
-                    luaurc.dependants.remove_by_right(path.clone());
-                }
-
-                let path_stem_str = path
-                    .file_stem()
-                    .map(|x| x.to_str().unwrap_or_default())
-                    .unwrap_or_else(|| "");
-                let _ = fs::remove_file(
-                    path.join(format!("../{}.model.json", path_stem_str))
-                        .normalize(),
-                );
-
-            // We can't decipher if the deleted path is a file or a directory,
-            // so we treat it as if it were a directory. This should be fine as
-            // we are only deleting dependencies whose path begins with this
-            // deleted path - if a match is found then it is indeed a directory.
-            } else {
-                self.prune_dependencies(&path);
-            }
-        }
-    }
-
-    fn create_file(&mut self, path: &Path, create_dependencies: CreateFileDependencies) {
-        let output_path = &{
-            let mut output_path = self
-                .output_dir
-                .join(path.strip_prefix(&self.input_dir).unwrap());
-            output_path.set_extension("model.json");
-            output_path
-        };
-
-        let _ = fs::create_dir_all(&output_path.parent().unwrap());
-
-        let model_json = rsml_to_model_json(&path, self);
-        fs::write(output_path, model_json).unwrap();
-
-        match create_dependencies {
-            CreateFileDependencies::True(referent_path) => {
-                let dependants = guarded_unwrap!(self.dependencies.get_by_right(path), return);
-
-                if let Some(referent_path) = referent_path {
-                    for dependant in dependants.clone() {
-                        if referent_path == dependant.as_ref() {
-                            continue;
-                        }
-
-                        self.create_file(&dependant, CreateFileDependencies::True(Some(path)));
-                    }
-                } else {
-                    for dependant in dependants.clone() {
-                        self.create_file(&dependant, CreateFileDependencies::True(Some(path)));
-                    }
-                };
-            }
-
-            CreateFileDependencies::False => (),
-        };
-    }
-
-    fn luaurc_update(&mut self, luaurc_path: PathBuf) {
-        let fresh_luaurc =
-            guarded_unwrap!(fs::read_to_string(&luaurc_path).map(Luaurc::new), return);
-
-        let (_, luaurc) = guarded_unwrap!(self.luaurc.take(), return);
-
-        let diff = fresh_luaurc.aliases.diff(&luaurc.aliases);
-
-        let new_luaurc = Luaurc {
-            aliases: Aliases(fresh_luaurc.aliases.0.clone()),
-            dependants: luaurc.dependants,
-            language_mode: fresh_luaurc.language_mode,
-        };
-        self.luaurc = Some((luaurc_path, new_luaurc));
-
-        let luaurc = &mut self.luaurc.as_mut().unwrap().1;
-
-        // Gets the files we need to update.
-        let mut to_update: HashSet<Ref<PathBuf>> = HashSet::new();
-        for key in diff {
-            let dependants_for_alias =
-                guarded_unwrap!(luaurc.dependants.get_by_left(key), continue);
-
-            to_update.extend(dependants_for_alias.iter().cloned());
-        }
-
-        for path in to_update {
-            self.create_file(path.0.as_path(), CreateFileDependencies::True(None));
-        }
-    }
+#[derive(Subcommand)]
+enum Commands {
+    Watch {
+        #[command(flatten)]
+        project: ProjectArgs,
 
-    // Removes any dependencies which start with the specified path.
-    fn prune_dependencies(&mut self, deleted_path: &Path) {
-        let keys_to_prune_from_dependencies = self
-            .dependencies
-            .left_to_right
-            .keys()
-            .filter_map(|key| {
-                if key.starts_with(deleted_path) {
-                    Some(key.to_path_buf())
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
+        #[command(flatten)]
+        compile: CompileArgs,
 
-        for key in keys_to_prune_from_dependencies {
-            self.dependencies.remove_by_left(key);
-        }
+        #[command(flatten)]
+        watch: WatchArgs,
+    },
 
-        if let Some((_, luaurc)) = self.luaurc.as_mut() {
-            let keys_to_prune_from_luaurc_dependants = luaurc
-                .dependants
-                .right_to_left
-                .keys()
-                .filter_map(|key| {
-                    if key.starts_with(deleted_path) {
-                        Some(key.to_path_buf())
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>();
-
-            for key in keys_to_prune_from_luaurc_dependants {
-                luaurc.dependants.remove_by_right(key);
-            }
-        }
-    }
+    Build {
+        #[command(flatten)]
+        project: ProjectArgs,
 
-    fn initialize(&mut self) {
-        if let Some((luaurc_path, _)) = &self.luaurc {
-            let _ = self.vfs.read(luaurc_path);
-        };
+        #[command(flatten)]
+        compile: CompileArgs,
+    },
 
-        self.recursive_scan(&PathBuf::new());
-    }
+    /// Removes every file which was generated from the project.
+    Clean {
+        #[command(flatten)]
+        project: ProjectArgs,
+    },
 
-    fn recursive_scan(&mut self, offset_dir: &PathBuf) {
-        if self.input_dir == self.output_dir {
-            let offset_input_dir = &self.input_dir.join(offset_dir).normalize();
+    /// Watches the input directory and serves the compiled stylesheets to a Studio plugin.
+    Serve {
+        #[command(flatten)]
+        project: ProjectArgs,
 
-            self.recursive_scan_create_and_clean(self.vfs.read_dir(offset_input_dir));
-        } else {
-            let offset_input_dir = &self.input_dir.join(offset_dir).normalize();
-            let offset_output_dir = &self.output_dir.join(offset_dir).normalize();
+        #[command(flatten)]
+        compile: CompileArgs,
 
-            self.recursive_scan_clean(self.vfs.read_dir(offset_output_dir));
-            self.recursive_scan_create(self.vfs.read_dir(offset_input_dir));
-        }
-    }
+        #[command(flatten)]
+        watch: WatchArgs,
 
-    fn recursive_scan_create_and_clean(&mut self, dir: Result<ReadDir, std::io::Error>) {
-        let dir = guarded_unwrap!(dir, return);
-
-        for entry in dir {
-            let path = guarded_unwrap!(&entry, continue).path();
-
-            // Applies files for all of the directories descendants.
-            if path.is_dir() {
-                self.recursive_scan_create_and_clean(self.vfs.read_dir(path));
-            } else if path.is_file() {
-                // Creates the .model.json for the current .rsml file.
-                if path.extension() == Some(OsStr::new("rsml")) {
-                    self.create_file(
-                        &dunce::canonicalize(&path).unwrap(),
-                        CreateFileDependencies::False,
-                    );
-
-                // Deletes .model.json file if it represents rsml as its considered stale.
-                } else if path.to_string_lossy().ends_with(".model.json")
-                    && model_json_is_rsml(path)
-                {
-                    let _ = fs::remove_file(path);
-                }
-            }
-        }
-    }
+        #[arg(long, default_value_t = 34873)]
+        port: u16,
+    },
 
-    fn recursive_scan_create(&mut self, dir: Result<ReadDir, std::io::Error>) {
-        let dir = guarded_unwrap!(dir, return);
+    Version,
+}
 
-        for entry in dir {
-            let path = guarded_unwrap!(&entry, continue).path();
+/// The arguments shared by every command which compiles a project.
+#[derive(Args)]
+struct ProjectArgs {
+    #[arg(value_enum, required = true)]
+    input: PathBuf,
 
-            // Applies files for all of the directories descendants.
-            if path.is_dir() {
-                self.recursive_scan_create(self.vfs.read_dir(path));
+    #[arg(short, long)]
+    output: Option<PathBuf>,
 
-            // Creates the .model.json for the current .rsml file.
-            } else if path.is_file() && path.extension() == Some(OsStr::new("rsml")) {
-                self.create_file(
-                    &dunce::canonicalize(&path).unwrap(),
-                    CreateFileDependencies::False,
-                );
-            }
-        }
-    }
+    #[arg(long = "luaurc")]
+    luaurc_path: Option<PathBuf>,
 
-    fn recursive_scan_clean(&mut self, dir: Result<ReadDir, std::io::Error>) {
-        let dir = guarded_unwrap!(dir, return);
+    #[arg(long = "root", value_parser = parse_root_arg)]
+    roots: Vec<RootArg>,
 
-        for entry in dir {
-            let path = guarded_unwrap!(&entry, continue).path();
+    /// Whether symlinks inside of the input directory are followed or skipped.
+    #[arg(long = "symlinks", value_enum, default_value_t = SymlinkPolicy::Follow)]
+    symlink_policy: SymlinkPolicy,
 
-            // Applies files for all of the directories descendants.
-            if path.is_dir() {
-                self.recursive_scan_clean(self.vfs.read_dir(path));
+    /// Warns about `.rsml` files which aren't plain UTF-8, such as those with a
+    /// byte order mark or encoded as UTF-16, which are otherwise decoded silently.
+    #[arg(long)]
+    warn_encoding: bool,
+}
 
-            // Removes the .model.json file.
-            } else if path.is_file()
-                && path.to_string_lossy().ends_with(".model.json")
-                && model_json_is_rsml(path)
-            {
-                let _ = fs::remove_file(path);
-            }
-        }
-    }
+/// The arguments shared by every command which writes compiled files.
+#[derive(Args, Default)]
+struct CompileArgs {
+    /// Overrides the language mode from directives and Luaurc files.
+    #[arg(long = "mode", value_enum)]
+    mode: Option<ModeArg>,
 
-    fn new(vfs: Vfs, input_dir: &Path, output_dir: &Path, luaurc_path: Option<&PathBuf>) -> Self {
-        let input_dir = dunce::canonicalize(input_dir).unwrap();
-        let output_dir = dunce::canonicalize(output_dir).unwrap();
-
-        Self {
-            vfs: Arc::new(vfs),
-            input_dir,
-            output_dir,
-            dependencies: MultiBiMap::new(),
-            luaurc: luaurc_path.map(|luaurc_path| {
-                let read_to_string = fs::read_to_string(&luaurc_path);
-
-                (
-                    luaurc_path.clone(),
-                    read_to_string.map(Luaurc::new).unwrap_or_default(),
-                )
-            }),
-        }
-    }
+    /// Overwrites `.model.json` files which weren't generated by RSML CLI.
+    #[arg(long)]
+    force: bool,
 }
 
-struct Watcher {
-    #[allow(unused)]
-    shutdown_sender: Sender<()>,
-
-    #[allow(unused)]
-    job_thread: JoinHandle<Result<(), RecvError>>,
+#[derive(Clone, Copy, ValueEnum)]
+enum ModeArg {
+    Strict,
+    Nonstrict,
 }
 
-impl Watcher {
-    fn start(mut context: WatcherContext) -> Watcher {
-        let start_time = Instant::now();
-
-        let vfs_receiver = context.vfs.event_receiver();
-
-        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded::<()>(1);
-
-        let job_thread: JoinHandle<Result<(), RecvError>> = jod_thread::Builder::new()
-            .name("ChangeProcessor thread".to_owned())
-            .spawn(move || {
-                loop {
-                    select! {
-                        recv(vfs_receiver) -> event => {
-                            match event {
-                                Ok(event) => {
-                                    // Prevents events from the build step from polluting the watcher.
-                                    // A bit of a band aid solution but it works.
-                                    if start_time.elapsed() > Duration::from_millis(200) {
-                                        context.handle_vfs_event(event)
-                                    }
-                                },
-                                Err(err) => println!("err: {}", err)
-                            }
-                        },
-
-                        recv(shutdown_receiver) -> _ => {
-                            return Ok(());
-                        }
-                    }
-                }
-            })
-            .expect("Could not start thread");
-
-        Self {
-            job_thread,
-            shutdown_sender,
+impl From<ModeArg> for LanguageMode {
+    fn from(mode: ModeArg) -> Self {
+        match mode {
+            ModeArg::Strict => LanguageMode::Strict,
+            ModeArg::Nonstrict => LanguageMode::Nonstrict,
         }
     }
 }
 
-#[derive(Parser)]
-#[command(name = "CLI")]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+/// The arguments shared by every command which watches a project.
+#[derive(Args)]
+struct WatchArgs {
+    /// Polls for changes at the interval instead of using native file notifications,
+    /// for file systems such as network shares and container mounts.
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        num_args = 0..=1,
+        default_missing_value = "1s",
+        value_parser = parse_interval
+    )]
+    poll: Option<Duration>,
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    Watch {
-        #[arg(value_enum, required = true)]
-        input: PathBuf,
-
-        #[arg(short, long)]
-        output: Option<PathBuf>,
-
-        #[arg(long = "luaurc")]
-        luaurc_path: Option<PathBuf>,
-    },
-
-    Build {
-        #[arg(value_enum, required = true)]
-        input: PathBuf,
+/// Parses an interval such as `500`, `500ms` or `2s`, where bare numbers are milliseconds.
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
 
-        #[arg(short, long)]
-        output: Option<PathBuf>,
+    let (number, unit) = match value.strip_suffix("ms") {
+        Some(number) => (number, 1),
+        None => match value.strip_suffix('s') {
+            Some(number) => (number, 1000),
+            None => (value, 1),
+        },
+    };
 
-        #[arg(long = "luaurc")]
-        luaurc_path: Option<PathBuf>,
-    },
+    let invalid = || "expected an interval such as `500ms` or `2s`".to_string();
 
-    Version,
-}
-
-trait FindFirstChild {
-    fn find_first_child<F>(&self, predicate: F) -> Option<PathBuf>
-    where
-        F: FnMut(&PathBuf) -> bool;
-}
+    let interval = number.trim().parse::<u64>().map_err(|_| invalid())?;
 
-impl FindFirstChild for Path {
-    fn find_first_child<F>(&self, mut predicate: F) -> Option<PathBuf>
-    where
-        F: FnMut(&PathBuf) -> bool,
-    {
-        for entry in fs::read_dir(self).ok()? {
-            let entry = entry.ok()?;
-            let path = entry.path();
-            if predicate(&path) {
-                return Some(path);
-            }
-        }
-
-        None
+    match interval {
+        0 => Err("the interval must be greater than zero".to_string()),
+        interval => interval
+            .checked_mul(unit)
+            .map(Duration::from_millis)
+            .ok_or_else(invalid),
     }
 }
 
-impl FindFirstChild for PathBuf {
-    fn find_first_child<F>(&self, predicate: F) -> Option<PathBuf>
-    where
-        F: FnMut(&PathBuf) -> bool,
-    {
-        self.as_path().find_first_child(predicate)
-    }
+#[derive(Clone)]
+struct RootArg {
+    id_prefix: Option<String>,
+    input: PathBuf,
+    output: Option<PathBuf>,
 }
 
-impl FindFirstChild for &PathBuf {
-    fn find_first_child<F>(&self, predicate: F) -> Option<PathBuf>
-    where
-        F: FnMut(&PathBuf) -> bool,
-    {
-        self.as_path().find_first_child(predicate)
-    }
-}
+// Parses a root in the form of `[PREFIX=]INPUT[=OUTPUT]`. The prefix and input end at the
+// first and second `=`, so only the output can contain `=`, unless the whole value is an
+// existing directory, which is always taken to be the input on its own.
+fn parse_root_arg(value: &str) -> Result<RootArg, String> {
+    let parts = match Path::new(value).is_dir() {
+        true => vec![value],
+        false => value.splitn(3, '=').collect::<Vec<_>>(),
+    };
 
-fn scan_for_luaurc(origin_dir: &PathBuf) -> Option<PathBuf> {
-    origin_dir.find_first_child(|path| {
-        if !path.is_file() {
-            return false;
-        };
+    let (id_prefix, input, output) = match parts.as_slice() {
+        [input] => (None, input, None),
+        [id_prefix, input] => (Some(id_prefix), input, None),
+        [id_prefix, input, output] => (Some(id_prefix), input, Some(output)),
+        _ => unreachable!(),
+    };
 
-        let prefix = path.file_prefix();
+    if input.is_empty() {
+        return Err("the root's input directory can't be empty".to_string());
+    }
 
-        prefix == Some(OsStr::new("luaurc"))
-            || prefix == Some(OsStr::new(".luaurc"))
-            || path.extension() == Some(OsStr::new("luaurc"))
+    Ok(RootArg {
+        id_prefix: id_prefix.map(|id_prefix| id_prefix.to_string()),
+        input: PathBuf::from(input),
+        output: output.map(PathBuf::from),
     })
 }
 
-enum LuaurcStatus {
-    // User specified luaurc path.
-    Some(PathBuf),
-
-    AutoSome(PathBuf),
-    AutoNone,
+/// A style root whose arguments have been resolved.
+struct Root {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    id_prefix: String,
 }
 
-impl LuaurcStatus {
-    fn as_option(&self) -> Option<&PathBuf> {
-        match self {
-            Self::Some(luaurc_path) | Self::AutoSome(luaurc_path) => Some(luaurc_path),
+fn resolve_root_arg(root: &RootArg) -> Result<Root, String> {
+    let input_dir = canonicalize_input(&root.input)?;
 
-            Self::AutoNone => None,
-        }
+    let id_prefix = match &root.id_prefix {
+        Some(id_prefix) => id_prefix.clone(),
+        None => input_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+
+    if id_prefix.is_empty() {
+        return Err(format!(
+            "ERROR: The style root {:#?} needs a prefix, specify one with `--root PREFIX=INPUT`.",
+            input_dir
+        ));
     }
+
+    let output_dir = root.output.clone().unwrap_or_else(|| input_dir.clone());
+
+    Ok(Root {
+        input_dir,
+        output_dir,
+        id_prefix,
+    })
 }
 
-fn resolve_luaurc_path(
-    input_dir: &PathBuf,
-    luaurc_path: Option<PathBuf>,
-) -> Result<LuaurcStatus, String> {
-    if let Some(luaurc_path) = luaurc_path {
-        match luaurc_path.canonicalize() {
-            Ok(luaurc_path) => match luaurc_path.is_file() {
-                true => return Ok(LuaurcStatus::Some(luaurc_path)),
-                false => (),
-            },
-            Err(_) => {
-                return Err(format!(
-                    "ERROR: Could not find Luaurc at {:#?}",
-                    luaurc_path.normalize()
-                ));
-            }
-        }
-    }
+fn resolve_luaurc_path(luaurc_path: Option<PathBuf>) -> Result<Option<PathBuf>, String> {
+    let luaurc_path = guarded_unwrap!(luaurc_path, return Ok(None));
 
-    match scan_for_luaurc(input_dir).or_else(|| scan_for_luaurc(&input_dir.join("../").normalize()))
-    {
-        Some(luaurc_path) => Ok(LuaurcStatus::AutoSome(luaurc_path)),
-        None => Ok(LuaurcStatus::AutoNone),
+    match dunce::canonicalize(&luaurc_path) {
+        Ok(luaurc_path) if luaurc_path.is_file() => Ok(Some(luaurc_path)),
+        _ => Err(format!(
+            "ERROR: Could not find Luaurc at {:#?}",
+            luaurc_path.normalize()
+        )),
     }
 }
 
@@ -526,7 +286,8 @@ fn startup_message(
     prefix: &str,
     input_dir: &PathBuf,
     output_dir: Option<&PathBuf>,
-    luaurc_path: &LuaurcStatus,
+    luaurc_path: Option<&PathBuf>,
+    discovered_luaurc_paths: &[&PathBuf],
 ) -> String {
     let to_output_str = if let Some(output_dir) = output_dir {
         &format!(" to {:#?}", output_dir)
@@ -534,22 +295,32 @@ fn startup_message(
         ""
     };
 
-    match luaurc_path {
-        LuaurcStatus::Some(luaurc_path) => format!(
-            "Using Luaurc at {:#?}.\n{} {:#?}{}.",
-            luaurc_path, prefix, input_dir, to_output_str
-        ),
-
-        LuaurcStatus::AutoSome(luaurc_path) => format!(
-            "Using Luaurc automatically found at {:#?}.\n{} {:#?}{}.",
-            luaurc_path, prefix, input_dir, to_output_str
-        ),
-
-        LuaurcStatus::AutoNone => format!(
-            "No Luaurc was specified or automatically found.\n{} {:#?}{}.",
-            prefix, input_dir, to_output_str
-        ),
-    }
+    let mut luaurc_lines: Vec<String> = luaurc_path
+        .map(|luaurc_path| format!("Using Luaurc at {:#?}.", luaurc_path))
+        .into_iter()
+        .collect();
+
+    luaurc_lines.extend(
+        discovered_luaurc_paths
+            .iter()
+            .map(|discovered_luaurc_path| {
+                format!(
+                    "Using Luaurc automatically found at {:#?}.",
+                    discovered_luaurc_path
+                )
+            }),
+    );
+
+    let luaurc_str = if luaurc_lines.is_empty() {
+        "No Luaurc was specified or automatically found.".to_string()
+    } else {
+        luaurc_lines.join("\n")
+    };
+
+    format!(
+        "{}\n{} {:#?}{}.",
+        luaurc_str, prefix, input_dir, to_output_str
+    )
 }
 
 fn canonicalize_input(path: &PathBuf) -> Result<PathBuf, String> {
@@ -568,15 +339,32 @@ fn canonicalize_input(path: &PathBuf) -> Result<PathBuf, String> {
     }
 }
 
-fn build(
-    input: PathBuf,
+/// A project whose arguments have been resolved, which hasn't been built yet.
+struct OpenProject {
+    context: WatcherContext,
+    input_dir: PathBuf,
     output: Option<PathBuf>,
     luaurc_path: Option<PathBuf>,
-    label: &str,
-) -> Option<WatcherContext> {
+    extra_roots: Vec<Root>,
+}
+
+fn open_project(
+    project: ProjectArgs,
+    compile: &CompileArgs,
+    poll: Option<Duration>,
+) -> Option<OpenProject> {
+    let ProjectArgs {
+        input,
+        output,
+        luaurc_path,
+        roots,
+        symlink_policy,
+        warn_encoding,
+    } = project;
+
     let mut stdout = stdout();
 
-    let input_dir = &match canonicalize_input(&input) {
+    let input_dir = match canonicalize_input(&input) {
         Ok(input_dir) => input_dir,
         Err(msg) => {
             let _ = writeln!(stdout, "{}", msg);
@@ -584,31 +372,143 @@ fn build(
         }
     };
 
-    let output_dir = output.as_ref().unwrap_or(input_dir);
+    let luaurc_path = match resolve_luaurc_path(luaurc_path) {
+        Ok(luaurc_path) => luaurc_path,
+
+        Err(msg) => {
+            let _ = writeln!(stdout, "{}", msg);
+            return None;
+        }
+    };
+
+    let mut extra_roots: Vec<Root> = Vec::new();
+    for root in &roots {
+        let root = match resolve_root_arg(root) {
+            Ok(root) => root,
+            Err(msg) => {
+                let _ = writeln!(stdout, "{}", msg);
+                return None;
+            }
+        };
+
+        if extra_roots
+            .iter()
+            .any(|extra_root| extra_root.id_prefix == root.id_prefix)
+        {
+            let _ = writeln!(
+                stdout,
+                "ERROR: The style root prefix {:#?} is used more than once!",
+                root.id_prefix
+            );
+            return None;
+        }
+
+        extra_roots.push(root);
+    }
+
+    let mut builder = WatcherContext::builder(&input_dir)
+        .language_mode(compile.mode.map(LanguageMode::from))
+        .force(compile.force)
+        .symlink_policy(symlink_policy)
+        .warn_encoding(warn_encoding)
+        .poll(poll);
+
+    if let Some(output) = &output {
+        builder = builder.output_dir(output);
+    }
+
+    if let Some(luaurc_path) = &luaurc_path {
+        builder = builder.luaurc(luaurc_path);
+    }
 
-    let luaurc_status = match resolve_luaurc_path(input_dir, luaurc_path) {
-        Ok(luaurc_status) => luaurc_status,
+    for root in &extra_roots {
+        builder = builder.root(&root.input_dir, &root.output_dir, &root.id_prefix);
+    }
 
+    let mut context = match builder.build() {
+        Ok(context) => context,
         Err(msg) => {
             let _ = writeln!(stdout, "{}", msg);
             return None;
         }
     };
-    let luaurc_path = luaurc_status.as_option();
+    context.add_listener(print_diagnostics());
+
+    Some(OpenProject {
+        context,
+        input_dir,
+        output,
+        luaurc_path,
+        extra_roots,
+    })
+}
+
+/// A listener which prints the problems found while building the project.
+fn print_diagnostics() -> OutputListener {
+    Box::new(|event| {
+        if let OutputEvent::Diagnostic(diagnostic) = event {
+            let _ = writeln!(stdout(), "{}", diagnostic);
+        }
+    })
+}
+
+fn build(
+    project: ProjectArgs,
+    compile: CompileArgs,
+    poll: Option<Duration>,
+    listener: Option<OutputListener>,
+    label: &str,
+    failed_label: Option<&str>,
+) -> Option<WatcherContext> {
+    let OpenProject {
+        mut context,
+        input_dir,
+        output,
+        luaurc_path,
+        extra_roots,
+    } = open_project(project, &compile, poll)?;
+
+    let mut stdout = stdout();
 
-    let _ = fs::create_dir_all(&input_dir);
-    let _ = fs::create_dir_all(&output_dir);
+    if let Some(listener) = listener {
+        context.add_listener(listener);
+    }
 
-    let vfs = Vfs::new(StdBackend::new());
-    let mut context = WatcherContext::new(vfs, &input_dir, &output_dir, luaurc_path);
     context.initialize();
 
+    let label = match failed_label {
+        Some(failed_label) if !context.failed.is_empty() || !context.conflicts().is_empty() => {
+            failed_label
+        }
+        _ => label,
+    };
+
     let _ = writeln!(
         stdout,
         "{}",
-        startup_message(label, input_dir, output.as_ref(), &luaurc_status)
+        startup_message(
+            label,
+            &input_dir,
+            output.as_ref(),
+            luaurc_path.as_ref(),
+            &context.discovered_luaurcs()
+        )
     );
 
+    for root in &extra_roots {
+        let to_output_str = if root.output_dir != root.input_dir {
+            &format!(" to {:#?}", root.output_dir)
+        } else {
+            ""
+        };
+
+        let _ = writeln!(
+            stdout,
+            "Including style root {:#?} as {:#?}{}.",
+            root.input_dir, root.id_prefix, to_output_str
+        );
+    }
+
     Some(context)
 }
 
@@ -636,26 +536,105 @@ fn main() {
 
     match cli.command {
         Commands::Watch {
-            input,
-            output,
-            luaurc_path,
+            project,
+            compile,
+            watch,
         } => {
             let context = guarded_unwrap!(
-                build(input, output, luaurc_path, "RSML CLI is now watching"),
+                build(
+                    project,
+                    compile,
+                    watch.poll,
+                    None,
+                    "RSML CLI is now watching",
+                    None
+                ),
                 return
             );
 
-            let _watcher = Watcher::start(context);
+            watch_until_stopped(context, watch.poll);
+        }
 
-            std::thread::park();
+        Commands::Build { project, compile } => {
+            let context = guarded_unwrap!(
+                build(
+                    project,
+                    compile,
+                    None,
+                    None,
+                    "RSML CLI successfully built",
+                    Some("RSML CLI failed to build")
+                ),
+                return
+            );
+
+            if !context.failed.is_empty() {
+                let _ = writeln!(
+                    stdout(),
+                    "ERROR: {} file(s) have errors in strict mode.",
+                    context.failed.len()
+                );
+            }
+
+            if !context.conflicts().is_empty() {
+                let _ = writeln!(
+                    stdout(),
+                    "ERROR: {} file(s) would overwrite files which weren't generated by RSML CLI.",
+                    context.conflicts().len()
+                );
+            }
+
+            if !context.failed.is_empty() || !context.conflicts().is_empty() {
+                std::process::exit(1);
+            }
         }
 
-        Commands::Build {
-            input,
-            output,
-            luaurc_path,
+        Commands::Clean { project } => {
+            let mut project =
+                guarded_unwrap!(open_project(project, &CompileArgs::default(), None), return);
+            let removed = project.context.clean();
+
+            let _ = writeln!(
+                stdout(),
+                "RSML CLI removed {} generated file(s) from {:#?}.",
+                removed,
+                project.input_dir
+            );
+        }
+
+        Commands::Serve {
+            project,
+            compile,
+            watch,
+            port,
         } => {
-            build(input, output, luaurc_path, "RSML CLI successfully built");
+            let state = Arc::new(ServeState::new());
+            state.add_listener(print_diagnostics());
+
+            let context = guarded_unwrap!(
+                build(
+                    project,
+                    compile,
+                    watch.poll,
+                    Some(state.listener()),
+                    "RSML CLI is now serving",
+                    None
+                ),
+                return
+            );
+
+            if let Err(msg) = state.start(port) {
+                let _ = writeln!(stdout(), "{}", msg);
+                return;
+            }
+
+            let _ = writeln!(
+                stdout(),
+                "Serving stylesheets at http://127.0.0.1:{}/api/rsml.",
+                port
+            );
+
+            watch_until_stopped(context, watch.poll);
         }
 
         Commands::Version => {
@@ -671,35 +650,51 @@ mod tests {
     use std::fs;
 
     #[test]
-    fn build_creates_model_json() {
-        let temp = std::env::temp_dir().join("rsml_test_build");
-        let input = temp.join("src");
-        let output = temp.join("out");
-
-        // Clean up from any previous run.
-        let _ = fs::remove_dir_all(&temp);
-        fs::create_dir_all(&input).unwrap();
-        fs::create_dir_all(&output).unwrap();
-
-        // Write an empty .rsml file (produces a valid model.json).
-        fs::write(input.join("test.rsml"), "").unwrap();
-
-        let vfs = Vfs::new(StdBackend::new());
-        let mut context = WatcherContext::new(vfs, &input, &output, None);
-        context.initialize();
-
-        let model_json_path = output.join("test.model.json");
-        assert!(
-            model_json_path.exists(),
-            "Expected {:?} to exist",
-            model_json_path
-        );
+    fn root_args_split_on_the_first_two_equals_signs() {
+        let root = parse_root_arg("shared=../shared=../out=v2").unwrap();
+        assert_eq!(root.id_prefix.as_deref(), Some("shared"));
+        assert_eq!(root.input, PathBuf::from("../shared"));
+        assert_eq!(root.output, Some(PathBuf::from("../out=v2")));
+
+        let root = parse_root_arg("../shared").unwrap();
+        assert_eq!(root.id_prefix, None);
+        assert_eq!(root.input, PathBuf::from("../shared"));
+
+        // Existing directories are taken as a whole, even when they contain `=`.
+        let temp = std::env::temp_dir().join("rsml_test_root_arg/a=b");
+        fs::create_dir_all(&temp).unwrap();
+
+        let root = parse_root_arg(temp.to_str().unwrap()).unwrap();
+        assert_eq!(root.id_prefix, None);
+        assert_eq!(root.input, temp);
 
-        let content: serde_json::Value =
-            serde_json::from_str(&fs::read_to_string(&model_json_path).unwrap()).unwrap();
-        assert_eq!(content["className"], "StyleSheet");
+        assert!(parse_root_arg("shared=").is_err());
 
-        // Clean up.
-        let _ = fs::remove_dir_all(&temp);
+        let _ = fs::remove_dir_all(temp.parent().unwrap());
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("250"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_interval("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_interval("2s"), Ok(Duration::from_secs(2)));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("soon").is_err());
+        assert!(parse_interval("18446744073709551615s").is_err());
+    }
+
+    #[test]
+    fn startup_message_lists_each_luaurc_on_its_own_line() {
+        let input_dir = PathBuf::from("src");
+        let first = PathBuf::from(".luaurc");
+        let second = PathBuf::from("src/.luaurc");
+
+        let message = startup_message("Building", &input_dir, None, None, &[&first, &second]);
+        assert_eq!(
+            message,
+            "Using Luaurc automatically found at \".luaurc\".\n\
+             Using Luaurc automatically found at \"src/.luaurc\".\n\
+             Building \"src\"."
+        );
     }
 }
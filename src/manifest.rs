@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Diagnostic, StyleRoot, atomic_write::write_atomic};
+
+/// The file in each output directory which lists the outputs the CLI generated.
+/// It has no `.json` extension so that Rojo doesn't sync it.
+pub const MANIFEST_FILE_NAME: &str = ".rsml-manifest";
+
+const MANIFEST_VERSION: u32 = 1;
+
+// FNV-1a, which is stable between runs unlike the standard library's hasher.
+pub fn content_hash(contents: &[u8]) -> u64 {
+    contents.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A `.model.json` file which was written by the CLI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedOutput {
+    /// The `.rsml` file it was compiled from.
+    pub source: PathBuf,
+
+    /// The hash of the contents it was written with.
+    pub hash: u64,
+}
+
+impl GeneratedOutput {
+    /// Whether the file at the path still has the contents it was generated with.
+    pub fn is_unchanged(&self, output_path: &Path) -> bool {
+        fs::read(output_path).is_ok_and(|contents| content_hash(&contents) == self.hash)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestFile {
+    version: u32,
+
+    /// Output paths relative to the output directory.
+    outputs: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    /// The source path relative to the input directory.
+    source: String,
+    hash: String,
+}
+
+pub fn manifest_path(root: &StyleRoot) -> PathBuf {
+    root.output_dir.join(MANIFEST_FILE_NAME)
+}
+
+// Paths are written with forward slashes so the manifest is the same on every platform.
+fn relative_path(path: &Path, base: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+
+    Some(
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Reads the outputs which were generated for the root, returning `None`
+/// if it has no manifest, such as a tree built by an older version of the CLI.
+pub fn load(
+    root: &StyleRoot,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<BTreeMap<PathBuf, GeneratedOutput>> {
+    let manifest_path = manifest_path(root);
+    let contents = fs::read_to_string(&manifest_path).ok()?;
+
+    let manifest = match serde_json::from_str::<ManifestFile>(&contents) {
+        Ok(manifest) if manifest.version == MANIFEST_VERSION => manifest,
+
+        Ok(_) | Err(_) => {
+            diagnostics.push(Diagnostic::warning(format!(
+                "Could not read the manifest at {:#?}, so generated files will be found by their contents instead.",
+                manifest_path
+            )));
+            return None;
+        }
+    };
+
+    let outputs = manifest
+        .outputs
+        .into_iter()
+        .filter_map(|(output, entry)| {
+            let hash = u64::from_str_radix(&entry.hash, 16).ok()?;
+
+            Some((
+                root.output_dir.join(output),
+                GeneratedOutput {
+                    source: root.input_dir.join(entry.source),
+                    hash,
+                },
+            ))
+        })
+        .collect();
+
+    Some(outputs)
+}
+
+/// Serializes the manifest for the outputs generated from the root.
+pub fn serialize<'a>(
+    root: &StyleRoot,
+    outputs: impl Iterator<Item = (&'a PathBuf, &'a GeneratedOutput)>,
+) -> String {
+    let outputs = outputs
+        .filter_map(|(output_path, generated)| {
+            Some((
+                relative_path(output_path, &root.output_dir)?,
+                ManifestEntry {
+                    source: relative_path(&generated.source, &root.input_dir)?,
+                    hash: format!("{:016x}", generated.hash),
+                },
+            ))
+        })
+        .collect();
+
+    let manifest = ManifestFile {
+        version: MANIFEST_VERSION,
+        outputs,
+    };
+
+    serde_json::to_string_pretty(&manifest).unwrap()
+}
+
+pub fn save(root: &StyleRoot, contents: &str) -> Result<(), Diagnostic> {
+    write_atomic(&manifest_path(root), contents).map_err(|err| {
+        Diagnostic::warning(format!(
+            "Could not write the manifest at {:#?}: {}",
+            manifest_path(root),
+            err
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifests_round_trip_with_relative_paths() {
+        let temp = std::env::temp_dir().join("rsml_test_manifest");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let root = StyleRoot::new(temp.join("src"), temp.clone(), String::new());
+        let mut diagnostics = Vec::new();
+        assert!(load(&root, &mut diagnostics).is_none());
+
+        let mut outputs = BTreeMap::new();
+        outputs.insert(
+            temp.join("nested/button.model.json"),
+            GeneratedOutput {
+                source: temp.join("src/nested/button.rsml"),
+                hash: content_hash(b"{}"),
+            },
+        );
+
+        let contents = serialize(&root, outputs.iter());
+        assert!(contents.contains("\"nested/button.model.json\""));
+        assert!(contents.contains("\"source\": \"nested/button.rsml\""));
+
+        save(&root, &contents).unwrap();
+        assert_eq!(load(&root, &mut diagnostics), Some(outputs));
+        assert!(diagnostics.is_empty());
+
+        // Manifests which can't be read fall back to finding generated files by their contents.
+        fs::write(manifest_path(&root), "{").unwrap();
+        assert!(load(&root, &mut diagnostics).is_none());
+        assert_eq!(diagnostics.len(), 1);
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+}
@@ -1,4 +1,4 @@
-use core::{borrow::Borrow, fmt, ops::Bound};
+use core::{borrow::Borrow, fmt};
 use std::{
     ops::{Deref, DerefMut},
     sync::Arc,
@@ -45,14 +45,6 @@ impl<T: ?Sized> Wrapper<T> {
         // safe because Wrapper<T> is #[repr(transparent)]
         unsafe { &*(value as *const T as *const Self) }
     }
-
-    pub fn wrap_bound(bound: Bound<&T>) -> Bound<&Self> {
-        match bound {
-            Bound::Included(t) => Bound::Included(Self::wrap(t)),
-            Bound::Excluded(t) => Bound::Excluded(Self::wrap(t)),
-            Bound::Unbounded => Bound::Unbounded,
-        }
-    }
 }
 
 impl<K, Q> Borrow<Wrapper<Q>> for Ref<K>
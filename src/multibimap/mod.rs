@@ -1,6 +1,6 @@
 use std::{
     borrow::Borrow,
-    collections::{BTreeMap, HashSet, btree_map::Entry},
+    collections::{BTreeMap, HashSet},
     fmt::Debug,
     hash::Hash,
     sync::Arc,
@@ -33,37 +33,15 @@ where
         let left = Ref(Arc::new(left));
         let right = Ref(Arc::new(right));
 
-        let right_map = self
-            .left_to_right
-            .entry(left.clone())
-            .or_insert(HashSet::new());
+        let right_map = self.left_to_right.entry(left.clone()).or_default();
         right_map.insert(right.clone());
 
-        let left_map = self
-            .right_to_left
-            .entry(right.clone())
-            .or_insert(HashSet::new());
+        let left_map = self.right_to_left.entry(right.clone()).or_default();
         left_map.insert(left.clone());
 
         (left_map, right_map)
     }
 
-    pub fn insert_by_left(&mut self, left: L) -> &mut HashSet<Ref<R>> {
-        let left = Ref(Arc::new(left));
-
-        self.left_to_right
-            .entry(left.clone())
-            .or_insert(HashSet::new())
-    }
-
-    pub fn insert_by_right(&mut self, right: R) -> &mut HashSet<Ref<L>> {
-        let right = Ref(Arc::new(right));
-
-        self.right_to_left
-            .entry(right.clone())
-            .or_insert(HashSet::new())
-    }
-
     pub fn remove_by_left(&mut self, left: L) {
         let left_ref = Ref(Arc::new(left));
 
@@ -108,26 +86,6 @@ where
         self.left_to_right.get(Wrapper::wrap(left))
     }
 
-    pub fn get_mut_by_left<Q>(&mut self, left: &Q) -> Option<&mut HashSet<Ref<R>>>
-    where
-        L: Borrow<Q>,
-        Q: Eq + Hash + ?Sized + Ord,
-    {
-        self.left_to_right.get_mut(Wrapper::wrap(left))
-    }
-
-    pub fn entry_by_left(&mut self, left: L) -> Entry<'_, Ref<L>, HashSet<Ref<R>>> {
-        let left = Ref(Arc::new(left));
-
-        self.left_to_right.entry(left)
-    }
-
-    pub fn entry_by_right(&mut self, right: R) -> Entry<'_, Ref<R>, HashSet<Ref<L>>> {
-        let right = Ref(Arc::new(right));
-
-        self.right_to_left.entry(right)
-    }
-
     pub fn get_by_right<Q>(&self, right: &Q) -> Option<&HashSet<Ref<L>>>
     where
         R: Borrow<Q>,
@@ -135,14 +93,6 @@ where
     {
         self.right_to_left.get(Wrapper::wrap(right))
     }
-
-    pub fn get_mut_by_right<Q>(&mut self, right: &Q) -> Option<&mut HashSet<Ref<L>>>
-    where
-        R: Borrow<Q>,
-        Q: Eq + Hash + ?Sized + Ord,
-    {
-        self.right_to_left.get_mut(Wrapper::wrap(right))
-    }
 }
 
 /*
@@ -0,0 +1,185 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::Receiver;
+use memofs::VfsEvent;
+
+use crate::symlinks::{SymlinkPolicy, is_symlink, is_symlink_loop};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    is_dir: bool,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// The modified time and size of every file beneath the polled paths.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Snapshot {
+    pub fn scan(paths: &[PathBuf], symlink_policy: SymlinkPolicy) -> Self {
+        let mut snapshot = Self::default();
+
+        for path in paths {
+            snapshot.scan_path(path, path, symlink_policy);
+        }
+
+        snapshot
+    }
+
+    fn scan_path(&mut self, path: &Path, base: &Path, symlink_policy: SymlinkPolicy) {
+        if path != base
+            && is_symlink(path)
+            && (symlink_policy == SymlinkPolicy::Skip || is_symlink_loop(path, base))
+        {
+            return;
+        }
+
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return,
+        };
+
+        self.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                is_dir: metadata.is_dir(),
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            },
+        );
+
+        if !metadata.is_dir() {
+            return;
+        }
+
+        if let Ok(read_dir) = fs::read_dir(path) {
+            for entry in read_dir.flatten() {
+                self.scan_path(&entry.path(), base, symlink_policy);
+            }
+        }
+    }
+
+    /// The events which turn this snapshot into the newer one.
+    pub fn changes(&self, newer: &Snapshot) -> Vec<VfsEvent> {
+        let mut events = Vec::new();
+
+        for (path, entry) in &self.entries {
+            match newer.entries.get(path) {
+                None => events.push(VfsEvent::Remove(path.clone())),
+
+                // A file replaced by a directory (or the other way around) is recreated.
+                Some(newer_entry) if newer_entry.is_dir != entry.is_dir => {
+                    events.push(VfsEvent::Remove(path.clone()));
+                    events.push(VfsEvent::Create(path.clone()));
+                }
+
+                // Directories change whenever their children do, which are already handled.
+                Some(newer_entry) if !entry.is_dir && newer_entry != entry => {
+                    events.push(VfsEvent::Write(path.clone()))
+                }
+
+                Some(_) => {}
+            }
+        }
+
+        for path in newer.entries.keys() {
+            if !self.entries.contains_key(path) {
+                events.push(VfsEvent::Create(path.clone()));
+            }
+        }
+
+        events
+    }
+}
+
+/// Polls the paths for changes on a background thread, for file systems such
+/// as network shares and container mounts where native notifications never arrive.
+pub fn spawn(
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    symlink_policy: SymlinkPolicy,
+) -> Receiver<VfsEvent> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::Builder::new()
+        .name("Poller thread".to_owned())
+        .spawn(move || {
+            let mut snapshot = Snapshot::scan(&paths, symlink_policy);
+
+            loop {
+                thread::sleep(interval);
+
+                let newer = Snapshot::scan(&paths, symlink_policy);
+                for event in snapshot.changes(&newer) {
+                    // The watcher has stopped.
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+                snapshot = newer;
+            }
+        })
+        .expect("Could not start thread");
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_paths(events: &[VfsEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                VfsEvent::Create(path) => format!("create {}", path.file_name().unwrap().display()),
+                VfsEvent::Write(path) => format!("write {}", path.file_name().unwrap().display()),
+                VfsEvent::Remove(path) => format!("remove {}", path.file_name().unwrap().display()),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_detect_created_written_and_removed_files() {
+        let temp = std::env::temp_dir().join("rsml_test_poll_snapshot");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+        fs::write(temp.join("button.rsml"), "").unwrap();
+        fs::write(temp.join("label.rsml"), "").unwrap();
+
+        let paths = vec![temp.clone()];
+        let scan = || Snapshot::scan(&paths, SymlinkPolicy::Follow);
+        let snapshot = scan();
+        assert!(snapshot.changes(&scan()).is_empty());
+
+        // Sizes are compared too, as modified times can be coarse on some file systems.
+        fs::write(temp.join("button.rsml"), "Frame {}").unwrap();
+        fs::remove_file(temp.join("label.rsml")).unwrap();
+        fs::create_dir_all(temp.join("nested")).unwrap();
+        fs::write(temp.join("nested/panel.rsml"), "").unwrap();
+
+        let newer = scan();
+        assert_eq!(
+            event_paths(&snapshot.changes(&newer)),
+            [
+                "write button.rsml",
+                "remove label.rsml",
+                "create nested",
+                "create panel.rsml"
+            ]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+}
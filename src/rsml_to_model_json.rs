@@ -1,23 +1,43 @@
 use std::{
     collections::{BTreeMap, HashSet},
-    fs,
+    ffi::OsStr,
     path::{Path, PathBuf},
 };
 
 use rbx_rsml::{
     RsmlCompiler, RsmlParser, compiler::tree_node::CompiledRsml, lexer::Token, parser::Construct,
+    types::LanguageMode,
 };
 use rbx_types::{Attributes, Variant};
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
 use serde_json::{Serializer as JsonSerializer, json, ser::PrettyFormatter};
 
-use crate::{NormalizePath, WatcherContext, guarded_unwrap, luaurc::Luaurc};
+use crate::{
+    Diagnostic, NormalizePath, Severity, StyleRoot, WatcherContext,
+    encoding::{SourceEncoding, read_source},
+    guarded_unwrap,
+    luaurc::{LuaurcChain, luau_comment_end},
+    symlinks::is_through_symlink,
+};
 
+/// A compiled `.rsml` file, which is serialized as a Rojo `.model.json` file.
 #[derive(Deserialize)]
 pub struct StyleSheet {
-    id: String,
-    attributes: Attributes,
-    children: Vec<Child>,
+    /// The path of the file relative to its root, which derives reference it by.
+    pub id: String,
+    pub attributes: Attributes,
+    pub children: Vec<Child>,
+}
+
+impl StyleSheet {
+    /// Serializes the stylesheet as the contents of a `.model.json` file.
+    pub fn to_model_json(&self) -> String {
+        let formatter = PrettyFormatter::with_indent(b"    ");
+        let mut buffer = Vec::new();
+        let mut serializer = JsonSerializer::with_formatter(&mut buffer, formatter);
+        self.serialize(&mut serializer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
 }
 
 impl Serialize for StyleSheet {
@@ -35,11 +55,12 @@ impl Serialize for StyleSheet {
 }
 
 #[derive(Deserialize)]
-struct StyleRule {
-    name: Option<String>,
-    attributes: Attributes,
-    properties: BTreeMap<String, Variant>,
-    children: Vec<Child>,
+pub struct StyleRule {
+    /// The selector of the rule.
+    pub name: Option<String>,
+    pub attributes: Attributes,
+    pub properties: BTreeMap<String, Variant>,
+    pub children: Vec<Child>,
 }
 
 impl Serialize for StyleRule {
@@ -65,9 +86,12 @@ impl Serialize for StyleRule {
 }
 
 #[derive(Deserialize)]
-struct StyleDerive {
-    name: String,
-    stylesheet: String,
+pub struct StyleDerive {
+    /// The name of the derived file without its extension.
+    pub name: String,
+
+    /// The id of the derived stylesheet.
+    pub stylesheet: String,
 }
 
 impl Serialize for StyleDerive {
@@ -90,86 +114,231 @@ impl Serialize for StyleDerive {
 
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
-enum Child {
+pub enum Child {
     StyleRule(StyleRule),
     StyleDerive(StyleDerive),
 }
 
-fn extract_derive_paths(source: &str) -> Vec<String> {
+/// File level directives such as `--!strict`, which are written before any code.
+#[derive(Debug, Default)]
+pub struct Directives {
+    /// Overrides the language mode from the Luaurc, but not the `--mode` flag.
+    pub language_mode: Option<LanguageMode>,
+
+    /// Set by `--!nooutput` for files which exist purely to be derived.
+    pub no_output: bool,
+
+    /// Directives which aren't recognised.
+    pub unknown: Vec<String>,
+}
+
+// Reads comments the same way Luau lexes them, so a `--!` inside of a long comment isn't a directive.
+fn parse_directives(source: &str) -> Directives {
+    let mut directives = Directives::default();
+    let bytes = source.as_bytes();
+    let mut idx = 0;
+
+    loop {
+        while bytes.get(idx).is_some_and(u8::is_ascii_whitespace) {
+            idx += 1;
+        }
+
+        // Like Luau, directives are only read until the first token of code.
+        if !bytes[idx..].starts_with(b"--") {
+            break;
+        }
+
+        let end = luau_comment_end(bytes, idx);
+        let comment = &source[idx..end];
+        idx = end;
+
+        let directive = guarded_unwrap!(comment.strip_prefix("--!"), continue).trim();
+
+        match directive {
+            "strict" => directives.language_mode = Some(LanguageMode::Strict),
+            "nonstrict" => directives.language_mode = Some(LanguageMode::Nonstrict),
+            "nooutput" => directives.no_output = true,
+            _ => directives.unknown.push(directive.to_string()),
+        }
+    }
+
+    directives
+}
+
+fn extract_derive_paths(source: &str) -> (Vec<String>, Directives) {
+    let directives = parse_directives(source);
+
     let parsed = RsmlParser::from_source(source);
-    parsed
+    let derives = parsed
         .ast
         .iter()
         .filter_map(|c| {
             if let Construct::Derive {
                 body: Some(body), ..
             } = c
+                && let Construct::Node { node } = body.as_ref()
             {
-                if let Construct::Node { node } = body.as_ref() {
-                    return match node.token.value() {
-                        Token::StringSingle(s) => Some(s.to_string()),
-                        Token::StringMulti(ms) => Some(ms.content.to_string()),
-                        _ => None,
-                    };
-                }
+                return match node.token.value() {
+                    Token::StringSingle(s) => Some(s.to_string()),
+                    Token::StringMulti(ms) => Some(ms.content.to_string()),
+                    _ => None,
+                };
             }
             None
         })
-        .collect()
+        .collect();
+
+    (derives, directives)
 }
 
+/// A derive statement which couldn't be resolved to an existing `.rsml` file.
+pub struct UnresolvedDerive {
+    /// The derive string as it was written in the source file.
+    pub derive: String,
+
+    /// The alias the derive started with and the value it expanded to.
+    pub alias: Option<(String, String)>,
+
+    /// The path the derive was expected to resolve to.
+    pub candidate: PathBuf,
+}
+
+enum DeriveError {
+    /// The derive resolved to the file which contains it.
+    SelfReference,
+
+    Unresolved(UnresolvedDerive),
+}
+
+// Resolves derives the same way Luau resolves require-by-string paths.
+// The `dependant` is the file being compiled, which is rebuilt whenever
+// an alias it went through (or attempted to go through) changes.
 fn resolve_derive_alias(
     derived_path: &str,
     current_path: &Path,
-    luaurc: Option<&mut (PathBuf, Luaurc)>,
-) -> PathBuf {
-    let path = 'core: {
-        let path = PathBuf::from(derived_path).normalize();
-        let (_, luaurc) = guarded_unwrap!(luaurc, break 'core path);
+    dependant: &Path,
+    luaurcs: &mut LuaurcChain,
+) -> (PathBuf, Option<(String, String)>) {
+    let current_dir = current_path.parent().unwrap_or(current_path);
+    let path = PathBuf::from(derived_path).normalize();
 
+    'alias: {
         let mut components = path.components();
 
-        let component = guarded_unwrap!(components.next(), break 'core path);
+        let component = guarded_unwrap!(components.next(), break 'alias);
         let component_str = component.as_os_str().to_string_lossy();
 
-        if component_str.starts_with("@")
-            && let Some(alias) = luaurc.aliases.get(&component_str.as_ref()[1..])
-        {
-            let mut path = PathBuf::from(alias);
-
-            path.push(components);
+        // Alias names are case insensitive.
+        let alias_name = guarded_unwrap!(component_str.strip_prefix("@"), break 'alias);
+        let alias_name = alias_name.to_lowercase();
 
-            return path;
-        } else {
-            path
+        // `@self` always refers to the directory containing the current file.
+        if alias_name == "self" {
+            return (
+                current_dir.join(components.as_path()),
+                Some((component_str.to_string(), current_dir.display().to_string())),
+            );
         }
-    };
 
-    current_path.join("../").join(path)
+        luaurcs
+            .dependants
+            .insert(alias_name.clone(), dependant.to_path_buf());
+
+        // Aliases come from every Luaurc above the file containing the derive.
+        let mut aliases = luaurcs.effective(current_dir).aliases;
+        let alias_path = guarded_unwrap!(aliases.remove(&alias_name), break 'alias);
+
+        return (
+            alias_path.join(components.as_path()),
+            Some((component_str.to_string(), alias_path.display().to_string())),
+        );
+    }
+
+    (current_dir.join(path), None)
 }
 
 fn resolve_derive(
     content: &str,
     current_path: &Path,
-    luaurc: Option<&mut (PathBuf, Luaurc)>,
-) -> Option<PathBuf> {
+    dependant: &Path,
+    roots: &[StyleRoot],
+    luaurcs: &mut LuaurcChain,
+) -> Result<PathBuf, DeriveError> {
     let content = content.trim();
-    let mut path = resolve_derive_alias(content, current_path, luaurc);
-    path.set_extension("rsml");
+    let (mut path, alias) = resolve_derive_alias(content, current_path, dependant, luaurcs);
+
+    // Appended rather than set, as names such as `button.hover` would otherwise lose their `.hover`.
+    if path.extension() != Some(OsStr::new("rsml")) {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".rsml");
+        path.set_file_name(file_name);
+    }
+
+    // Normalizes before canonicalizing as `file.rsml/..` isn't a valid path on unix.
+    let path = path.normalize();
 
     match path.canonicalize() {
         Ok(canonicalized) => {
-            if &canonicalized == current_path {
-                None
+            // Derives through a symlink inside of a root keep the path they're linked at, which is the
+            // path they were scanned at. Symlinks above the root, such as `/tmp` on macOS, are resolved.
+            let resolved = match roots
+                .iter()
+                .any(|root| is_through_symlink(&path, &root.input_dir))
+            {
+                true => path.clone(),
+                false => canonicalized,
+            };
+
+            if resolved == *current_path {
+                Err(DeriveError::SelfReference)
             } else {
-                Some(canonicalized)
+                Ok(resolved)
             }
         }
 
-        Err(_) => None,
+        Err(_) => Err(DeriveError::Unresolved(UnresolvedDerive {
+            derive: content.to_string(),
+            alias,
+            candidate: path,
+        })),
     }
 }
 
+// Problems with directives and derives are errors in strict mode, which stop the file from being compiled.
+fn severity(language_mode: &LanguageMode) -> Severity {
+    match language_mode {
+        LanguageMode::Strict => Severity::Error,
+        LanguageMode::Nonstrict => Severity::Warning,
+    }
+}
+
+fn unresolved_derive_message(path: &Path, unresolved: &UnresolvedDerive) -> String {
+    let alias_str = if let Some((alias, expansion)) = &unresolved.alias {
+        &format!("\n    Alias {:#?} expanded to {:#?}.", alias, expansion)
+    } else {
+        ""
+    };
+
+    format!(
+        "Could not resolve derive {:#?} in {:#?}.{}\n    No file exists at {:#?}.",
+        unresolved.derive, path, alias_str, unresolved.candidate
+    )
+}
+
+fn outside_roots_message(path: &Path, derive: &str, derive_path: &Path) -> String {
+    format!(
+        "Derive {:#?} in {:#?} resolves to {:#?}, which is outside of every style root.\n    Register its directory with `--root` so it can be referenced.",
+        derive, path, derive_path
+    )
+}
+
+fn no_output_derive_message(path: &Path, derive: &str, derive_path: &Path) -> String {
+    format!(
+        "Derive {:#?} in {:#?} resolves to {:#?}, which has the `--!nooutput` directive.\n    It isn't compiled, so its rules aren't applied.",
+        derive, path, derive_path
+    )
+}
+
 fn convert_children(compiled: &mut CompiledRsml, children: Vec<usize>) -> Vec<Child> {
     children
         .iter()
@@ -212,89 +381,604 @@ fn convert_children(compiled: &mut CompiledRsml, children: Vec<usize>) -> Vec<Ch
         .collect::<Vec<Child>>()
 }
 
+// Derives are resolved relative to the file which contains them,
+// matching the semantics of Luau's `require`. Files with the `--!nooutput`
+// directive are collected while they're parsed, so they're only read once.
 fn track_derive_dependencies(
     derive_path: PathBuf,
     path: &Path,
     already_tracked: &mut HashSet<PathBuf>,
+    no_output: &mut HashSet<PathBuf>,
     watcher: &mut WatcherContext,
 ) {
-    if let Ok(derive_content) = fs::read_to_string(&derive_path) {
-        let derives = extract_derive_paths(&derive_content);
+    if let Ok((derive_content, _)) = read_source(&derive_path) {
+        let (derives, directives) = extract_derive_paths(&derive_content);
+        if directives.no_output {
+            no_output.insert(derive_path.clone());
+        }
+
         for derive in derives {
-            let derive_path = guarded_unwrap!(
-                resolve_derive(&derive, path, watcher.luaurc.as_mut()),
+            // Unresolved derives are reported when the derived file itself is compiled.
+            let nested_derive_path = guarded_unwrap!(
+                resolve_derive(
+                    &derive,
+                    &derive_path,
+                    path,
+                    &watcher.roots,
+                    &mut watcher.luaurcs
+                ),
                 continue
             );
 
-            if already_tracked.contains(&derive_path) {
+            // Marks the path as tracked before recursing so cyclic derives terminate.
+            if nested_derive_path == path || !already_tracked.insert(nested_derive_path.clone()) {
                 continue;
             }
 
-            track_derive_dependencies(derive_path.clone(), path, already_tracked, watcher);
-
-            already_tracked.insert(derive_path);
+            track_derive_dependencies(
+                nested_derive_path,
+                path,
+                already_tracked,
+                no_output,
+                watcher,
+            );
         }
     }
 
     watcher.dependencies.insert(path.to_path_buf(), derive_path);
 }
 
-pub fn rsml_to_model_json(path: &Path, watcher: &mut WatcherContext) -> String {
-    let content = fs::read_to_string(path).unwrap();
+/// The result of compiling an `.rsml` file.
+pub enum RsmlOutput {
+    StyleSheet(StyleSheet),
+
+    /// The file has the `--!nooutput` directive.
+    NoOutput,
+
+    /// The file has errors in strict mode.
+    Failed,
+
+    /// The file couldn't be read, such as when it isn't valid UTF-8 or UTF-16.
+    Unreadable,
+}
+
+/// Compiles the source on its own. Derives can't be resolved without the file they're
+/// written in, so they're left out, use [`crate::compile_file`] to resolve them.
+pub fn compile_source(source: &str, id: &str) -> StyleSheet {
+    let mut compiled = RsmlCompiler::from_source(source);
+
+    let rsml_root = compiled.take_root().unwrap();
+
+    StyleSheet {
+        id: id.to_string(),
+        attributes: rsml_root.attributes,
+        children: convert_children(&mut compiled, rsml_root.child_rules),
+    }
+}
+
+pub fn compile_rsml(path: &Path, watcher: &mut WatcherContext) -> RsmlOutput {
+    let content = match read_source(path) {
+        Ok((content, encoding)) => {
+            if watcher.warn_encoding && encoding != SourceEncoding::Utf8 {
+                watcher.report(Diagnostic::warning(format!(
+                    "{:#?} is encoded as {}, rather than plain UTF-8.",
+                    path, encoding
+                )));
+            }
+            content
+        }
+
+        Err(err) => {
+            watcher.report(Diagnostic::error(format!(
+                "Could not read {:#?}: {}",
+                path, err
+            )));
+            return RsmlOutput::Unreadable;
+        }
+    };
+
+    let (derive_strings, directives) = extract_derive_paths(&content);
 
-    let derive_strings = extract_derive_paths(&content);
+    let language_mode = watcher.language_mode(path, directives.language_mode);
+    let severity = severity(&language_mode);
+    let mut errors = 0;
+
+    for directive in &directives.unknown {
+        watcher.report(Diagnostic {
+            severity,
+            message: format!("Unknown directive \"--!{}\" in {:#?}.", directive, path),
+        });
+        errors += 1;
+    }
 
     let mut already_tracked: HashSet<PathBuf> = HashSet::new();
+    let mut no_output: HashSet<PathBuf> = HashSet::new();
+
+    watcher
+        .unresolved_derives
+        .remove_by_left(path.to_path_buf());
+
+    // The aliases this file depends on are recorded again as its derives are resolved.
+    watcher
+        .luaurcs
+        .dependants
+        .remove_by_right(path.to_path_buf());
 
     let derives_children = derive_strings
         .iter()
         .filter_map(|derive| {
-            let derive_path = guarded_unwrap!(
-                resolve_derive(&derive, path, watcher.luaurc.as_mut()),
-                return None
-            );
+            let derive_path =
+                match resolve_derive(derive, path, path, &watcher.roots, &mut watcher.luaurcs) {
+                    Ok(derive_path) => derive_path,
+
+                    Err(DeriveError::Unresolved(unresolved)) => {
+                        watcher.report(Diagnostic {
+                            severity,
+                            message: unresolved_derive_message(path, &unresolved),
+                        });
+                        errors += 1;
+
+                        // Lets the dependant be rebuilt once the missing file is created.
+                        watcher
+                            .unresolved_derives
+                            .insert(path.to_path_buf(), unresolved.candidate);
+
+                        return None;
+                    }
+
+                    Err(DeriveError::SelfReference) => return None,
+                };
+
+            if already_tracked.insert(derive_path.clone()) {
+                track_derive_dependencies(
+                    derive_path.clone(),
+                    path,
+                    &mut already_tracked,
+                    &mut no_output,
+                    watcher,
+                );
+            }
+
+            // There's no stylesheet to reference, but the derive is still tracked
+            // so this file is rebuilt if the directive is removed.
+            if no_output.contains(&derive_path) {
+                watcher.report(Diagnostic {
+                    severity,
+                    message: no_output_derive_message(path, derive, &derive_path),
+                });
+                errors += 1;
+
+                return None;
+            }
+
+            // The derived stylesheet can only be referenced if it's compiled by one of our roots.
+            let stylesheet = match watcher
+                .style_root(&derive_path)
+                .and_then(|root| root.id(&derive_path))
+            {
+                Some(stylesheet) => stylesheet,
+
+                None => {
+                    watcher.report(Diagnostic {
+                        severity,
+                        message: outside_roots_message(path, derive, &derive_path),
+                    });
+                    errors += 1;
 
-            track_derive_dependencies(derive_path.clone(), path, &mut already_tracked, watcher);
+                    return None;
+                }
+            };
 
             Some(Child::StyleDerive(StyleDerive {
                 name: derive_path
                     .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-                stylesheet: derive_path
-                    .strip_prefix(&watcher.input_dir)
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
+                    .unwrap_or_default()
+                    .to_string_lossy()
                     .to_string(),
+                stylesheet,
             }))
         })
         .collect::<Vec<Child>>();
 
-    let mut compiled = RsmlCompiler::from_source(&content);
+    if errors > 0 && matches!(language_mode, LanguageMode::Strict) {
+        return RsmlOutput::Failed;
+    }
+
+    if directives.no_output {
+        return RsmlOutput::NoOutput;
+    }
 
-    let rsml_root = compiled.take_root().unwrap();
+    let id = watcher
+        .style_root(path)
+        .and_then(|root| root.id(path))
+        .unwrap_or_default();
 
-    let mut children = convert_children(&mut compiled, rsml_root.child_rules);
-    children.extend(derives_children);
+    let mut style_sheet = compile_source(&content, &id);
+    style_sheet.children.extend(derives_children);
 
-    let style_sheet = StyleSheet {
-        id: path
-            .normalize()
-            .strip_prefix(&watcher.input_dir)
-            .unwrap()
-            .to_str()
+    RsmlOutput::StyleSheet(style_sheet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memofs::{StdBackend, Vfs};
+    use std::fs;
+
+    fn setup(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let temp = std::env::temp_dir().join(name);
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        for (file, contents) in files {
+            let file_path = temp.join(file);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, contents).unwrap();
+        }
+
+        dunce::canonicalize(temp).unwrap()
+    }
+
+    fn build(input: &Path, luaurc_path: Option<&PathBuf>) -> WatcherContext {
+        let vfs = Vfs::new(StdBackend::new());
+        let mut context = WatcherContext::new(vfs, input, input, luaurc_path).unwrap();
+        context.initialize();
+        context
+    }
+
+    fn dependencies_of(context: &WatcherContext, path: &Path) -> Vec<PathBuf> {
+        let mut dependencies = context
+            .dependencies
+            .get_by_left(path)
+            .map(|dependencies| {
+                dependencies
+                    .iter()
+                    .map(|dependency| dependency.to_path_buf())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        dependencies.sort();
+        dependencies
+    }
+
+    #[test]
+    fn nested_relative_derives_resolve_from_derived_file() {
+        let temp = setup(
+            "rsml_test_nested_relative_derives",
+            &[
+                ("button.rsml", "@derive \"./themes/dark\";\n"),
+                ("themes/dark.rsml", "@derive \"./base\";\n"),
+                ("themes/base.rsml", ""),
+                // Would be picked up if nested derives resolved relative to `button.rsml`.
+                ("base.rsml", ""),
+            ],
+        );
+
+        let context = build(&temp, None);
+
+        assert_eq!(
+            dependencies_of(&context, &temp.join("button.rsml")),
+            vec![temp.join("themes/base.rsml"), temp.join("themes/dark.rsml")]
+        );
+        assert_eq!(
+            dependencies_of(&context, &temp.join("themes/dark.rsml")),
+            vec![temp.join("themes/base.rsml")]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn multi_level_parent_derives_resolve_from_derived_file() {
+        let temp = setup(
+            "rsml_test_multi_level_parent_derives",
+            &[
+                ("ui/menus/pause.rsml", "@derive \"../shared/panel\";\n"),
+                ("ui/shared/panel.rsml", "@derive \"../../tokens/colors\";\n"),
+                ("tokens/colors.rsml", "@derive \"./spacing\";\n"),
+                ("tokens/spacing.rsml", ""),
+            ],
+        );
+
+        let context = build(&temp, None);
+
+        assert_eq!(
+            dependencies_of(&context, &temp.join("ui/menus/pause.rsml")),
+            vec![
+                temp.join("tokens/colors.rsml"),
+                temp.join("tokens/spacing.rsml"),
+                temp.join("ui/shared/panel.rsml"),
+            ]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn aliased_derives_resolve_nested_derives_from_derived_file() {
+        let temp = setup(
+            "rsml_test_aliased_nested_derives",
+            &[
+                ("src/button.rsml", "@derive \"@themes/dark\";\n"),
+                ("themes/dark.rsml", "@derive \"./base\";\n"),
+                ("themes/base.rsml", ""),
+                ("src/base.rsml", ""),
+            ],
+        );
+
+        let luaurc_path = temp.join(".luaurc");
+        fs::write(
+            &luaurc_path,
+            serde_json::to_string(&json!({
+                "aliases": { "themes": temp.join("themes") }
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+
+        let input = temp.join("src");
+        let context = build(&input, Some(&luaurc_path));
+
+        assert_eq!(
+            dependencies_of(&context, &input.join("button.rsml")),
+            vec![temp.join("themes/base.rsml"), temp.join("themes/dark.rsml")]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn cyclic_derives_terminate() {
+        let temp = setup(
+            "rsml_test_cyclic_derives",
+            &[
+                ("a.rsml", "@derive \"./b\";\n"),
+                ("b.rsml", "@derive \"./a\";\n"),
+            ],
+        );
+
+        let context = build(&temp, None);
+
+        assert_eq!(
+            dependencies_of(&context, &temp.join("a.rsml")),
+            vec![temp.join("b.rsml")]
+        );
+        assert_eq!(
+            dependencies_of(&context, &temp.join("b.rsml")),
+            vec![temp.join("a.rsml")]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn aliases_resolve_relative_to_luaurc_case_insensitively() {
+        let temp = setup(
+            "rsml_test_luaurc_relative_aliases",
+            &[
+                (
+                    ".luaurc",
+                    "{ \"aliases\": { \"Themes\": \"./src/themes\" } }",
+                ),
+                ("src/ui/button.rsml", "@derive \"@THEMES/dark\";\n"),
+                ("src/themes/dark.rsml", ""),
+            ],
+        );
+
+        let input = temp.join("src");
+        let context = build(&input, Some(&temp.join(".luaurc")));
+
+        assert_eq!(
+            dependencies_of(&context, &input.join("ui/button.rsml")),
+            vec![input.join("themes/dark.rsml")]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn config_luau_aliases_take_precedence_over_luaurc() {
+        let temp = setup(
+            "rsml_test_config_luau_aliases",
+            &[
+                (".luaurc", "{ \"aliases\": { \"themes\": \"./legacy\" } }"),
+                (
+                    ".config.luau",
+                    "-- Shared configuration.\nreturn {\n\tluau = {\n\t\tlanguagemode = \"strict\",\n\t\taliases = { Themes = \"./themes\" },\n\t},\n}\n",
+                ),
+                ("button.rsml", "@derive \"@themes/dark\";\n"),
+                ("themes/dark.rsml", ""),
+                ("legacy/dark.rsml", ""),
+            ],
+        );
+
+        let context = build(&temp, None);
+
+        assert_eq!(
+            dependencies_of(&context, &temp.join("button.rsml")),
+            vec![temp.join("themes/dark.rsml")]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn directives_are_only_read_before_code() {
+        let directives = parse_directives(
+            "-- Tokens shared by every theme.\n--!strict\n--[[\n--!nonstrict\n]]\n--!nooutput\n\n--!optimize 2\nFrame {\n}\n--!nonstrict\n",
+        );
+
+        assert!(matches!(
+            directives.language_mode,
+            Some(LanguageMode::Strict)
+        ));
+        assert!(directives.no_output);
+        assert_eq!(directives.unknown, vec!["optimize 2".to_string()]);
+    }
+
+    #[test]
+    fn nooutput_files_are_tracked_but_not_written_or_derived() {
+        let temp = setup(
+            "rsml_test_nooutput_directive",
+            &[
+                (".luaurc", "{ \"languageMode\": \"strict\" }"),
+                ("tokens.rsml", "--!nooutput\n"),
+                (
+                    "button.rsml",
+                    "--!nonstrict\n@derive \"./tokens\";\n@derive \"./missing\";\n",
+                ),
+                ("label.rsml", "@derive \"./tokens\";\n"),
+            ],
+        );
+
+        let context = build(&temp, None);
+
+        assert!(!temp.join("tokens.model.json").exists());
+        assert_eq!(
+            dependencies_of(&context, &temp.join("button.rsml")),
+            vec![temp.join("tokens.rsml")]
+        );
+
+        // The file level directive overrides the strict Luaurc, so the missing derive is only a warning.
+        let button: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp.join("button.model.json")).unwrap())
+                .unwrap();
+        assert_eq!(button["children"], serde_json::json!([]));
+
+        // Deriving a file which isn't compiled is an error in strict mode.
+        assert!(!temp.join("label.model.json").exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn self_alias_resolves_relative_to_current_file() {
+        let temp = setup(
+            "rsml_test_self_alias",
+            &[
+                ("ui/button.rsml", "@derive \"@self/shared\";\n"),
+                ("ui/shared.rsml", ""),
+            ],
+        );
+
+        let context = build(&temp, None);
+
+        assert_eq!(
+            dependencies_of(&context, &temp.join("ui/button.rsml")),
+            vec![temp.join("ui/shared.rsml")]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    fn read_model_json(path: &Path) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn unusual_names_have_consistent_ids() {
+        let temp = setup(
+            "rsml_test_unusual_names",
+            &[
+                (
+                    "main.rsml",
+                    "@derive \"./button.hover\";\n@derive \"./my button\";\n@derive \"./thèmes/ボタン\";\n",
+                ),
+                ("button.hover.rsml", ""),
+                ("my button.rsml", ""),
+                ("thèmes/ボタン.rsml", ""),
+            ],
+        );
+
+        build(&temp, None);
+
+        for (output, id) in [
+            ("button.hover.model.json", "button.hover.rsml"),
+            ("my button.model.json", "my button.rsml"),
+            ("thèmes/ボタン.model.json", "thèmes/ボタン.rsml"),
+        ] {
+            assert_eq!(read_model_json(&temp.join(output))["id"], id);
+        }
+
+        // Dotted names are derived as a whole, rather than as `button.rsml`.
+        let main = read_model_json(&temp.join("main.model.json"));
+        let derives = main["children"]
+            .as_array()
             .unwrap()
-            .to_string(),
-        attributes: rsml_root.attributes,
-        children,
-    };
+            .iter()
+            .map(|derive| {
+                (
+                    derive["name"].as_str().unwrap(),
+                    derive["attributes"]["Rojo_Target_StyleSheet"]
+                        .as_str()
+                        .unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            derives,
+            [
+                ("button.hover", "button.hover.rsml"),
+                ("my button", "my button.rsml"),
+                ("ボタン", "thèmes/ボタン.rsml")
+            ]
+        );
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_skipped() {
+        use std::os::unix::ffi::OsStrExt;
 
-    let formatter = PrettyFormatter::with_indent(b"    ");
-    let mut buffer = Vec::new();
-    let mut serializer = JsonSerializer::with_formatter(&mut buffer, formatter);
-    style_sheet.serialize(&mut serializer).unwrap();
-    String::from_utf8(buffer).unwrap()
+        let temp = setup("rsml_test_non_utf8_names", &[("button.rsml", "")]);
+
+        let name = OsStr::from_bytes(b"bad\xff.rsml");
+        if fs::write(temp.join(name), "").is_err() {
+            // Some file systems refuse names which aren't valid UTF-8.
+            let _ = fs::remove_dir_all(&temp);
+            return;
+        }
+
+        build(&temp, None);
+
+        assert!(temp.join("button.model.json").exists());
+        assert!(!temp.join(OsStr::from_bytes(b"bad\xff.model.json")).exists());
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    #[test]
+    fn sources_with_byte_order_marks_are_decoded() {
+        let source = "Frame {\n    Name = \"wörld\";\n}\n";
+        let temp = setup("rsml_test_source_encodings", &[("plain.rsml", source)]);
+
+        fs::write(
+            temp.join("bom.rsml"),
+            SourceEncoding::Utf8Bom.encode(source),
+        )
+        .unwrap();
+        fs::write(
+            temp.join("utf16.rsml"),
+            SourceEncoding::Utf16Le.encode(source),
+        )
+        .unwrap();
+        fs::write(temp.join("invalid.rsml"), b"Frame \xFF").unwrap();
+
+        let context = build(&temp, None);
+
+        let plain = read_model_json(&temp.join("plain.model.json"));
+        for name in ["bom", "utf16"] {
+            let decoded = read_model_json(&temp.join(format!("{}.model.json", name)));
+            assert_eq!(decoded["children"], plain["children"]);
+        }
+
+        // Files which can't be decoded are reported rather than crashing the build.
+        assert!(!temp.join("invalid.model.json").exists());
+        assert_eq!(context.stats.errors, 1);
+
+        let _ = fs::remove_dir_all(&temp);
+    }
 }
@@ -0,0 +1,498 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::PathBuf,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::crate_version;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{Diagnostic, OutputEvent, OutputListener, guarded_unwrap, write_back::write_back};
+
+/// Bumped whenever the shape of the api changes so plugins can detect it.
+const PROTOCOL_VERSION: u64 = 1;
+
+/// How long a subscription is held open for before returning with no changes.
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The header the Studio plugin sends with requests which write back to files.
+const PLUGIN_HEADER: &str = "X-RSML-Plugin";
+
+/// How many changes are kept for subscribers which are behind.
+const MAX_CHANGES: usize = 1000;
+
+/// How many requests are handled at once.
+const WORKERS: usize = 8;
+
+/// How many subscriptions can be held open at once. Each one holds a worker,
+/// so the rest are always free to handle other requests.
+const MAX_SUBSCRIBERS: usize = WORKERS / 2;
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum Change {
+    Updated { id: String, stylesheet: Value },
+    Removed { id: String },
+}
+
+struct Published {
+    path: PathBuf,
+    stylesheet: Value,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Stylesheet ids mapped to their latest compiled stylesheet.
+    stylesheets: BTreeMap<String, Published>,
+
+    /// The most recent changes, the last of which is at `cursor`.
+    changes: VecDeque<Change>,
+    cursor: u64,
+}
+
+/// The compiled stylesheets shared between the watcher and the server.
+pub struct ServeState {
+    inner: Mutex<Inner>,
+    changed: Condvar,
+    session_id: String,
+    subscribers: AtomicUsize,
+
+    /// Called with the problems found while serving, such as values which couldn't be written back.
+    listeners: Mutex<Vec<OutputListener>>,
+}
+
+impl Default for ServeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServeState {
+    pub fn new() -> Self {
+        let session_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+
+        Self {
+            inner: Mutex::new(Inner::default()),
+            changed: Condvar::new(),
+            session_id: format!("{:x}", session_id),
+            subscribers: AtomicUsize::new(0),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Calls the listener with each problem found while serving.
+    pub fn add_listener(&self, listener: OutputListener) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    fn report(&self, diagnostic: Diagnostic) {
+        for listener in self.listeners.lock().unwrap().iter_mut() {
+            listener(&OutputEvent::Diagnostic(&diagnostic));
+        }
+    }
+
+    /// A listener which publishes the watcher's outputs.
+    pub fn listener(self: &Arc<Self>) -> OutputListener {
+        let state = self.clone();
+        Box::new(move |event| state.publish(event))
+    }
+
+    /// Starts serving the stylesheets on a background thread.
+    pub fn start(self: &Arc<Self>, port: u16) -> Result<(), String> {
+        spawn_server(self.clone(), port)
+    }
+
+    fn publish(&self, event: &OutputEvent) {
+        let mut inner = self.inner.lock().unwrap();
+
+        match event {
+            OutputEvent::Compiled {
+                path,
+                id,
+                model_json,
+            } => {
+                let stylesheet = guarded_unwrap!(serde_json::from_str::<Value>(model_json), return);
+
+                inner.stylesheets.insert(
+                    id.to_string(),
+                    Published {
+                        path: path.to_path_buf(),
+                        stylesheet: stylesheet.clone(),
+                    },
+                );
+                inner.push(Change::Updated {
+                    id: id.to_string(),
+                    stylesheet,
+                });
+            }
+
+            // Removals may be of a directory, which removes every stylesheet inside of it.
+            OutputEvent::Removed { path } => {
+                let removed = inner
+                    .stylesheets
+                    .iter()
+                    .filter(|(_, published)| published.path.starts_with(path))
+                    .map(|(id, _)| id.clone())
+                    .collect::<Vec<_>>();
+
+                for id in removed {
+                    inner.stylesheets.remove(&id);
+                    inner.push(Change::Removed { id });
+                }
+            }
+
+            // Problems are printed by the CLI rather than sent to the plugin.
+            OutputEvent::Diagnostic(_) => {}
+        }
+
+        self.changed.notify_all();
+    }
+
+    fn info(&self) -> Value {
+        let inner = self.inner.lock().unwrap();
+
+        json!({
+            "serverVersion": crate_version!(),
+            "protocolVersion": PROTOCOL_VERSION,
+            "sessionId": self.session_id,
+            "cursor": inner.cursor,
+        })
+    }
+
+    fn stylesheets(&self) -> Value {
+        let inner = self.inner.lock().unwrap();
+
+        let stylesheets = inner
+            .stylesheets
+            .iter()
+            .map(|(id, published)| (id.clone(), published.stylesheet.clone()))
+            .collect::<serde_json::Map<_, _>>();
+
+        json!({
+            "sessionId": self.session_id,
+            "cursor": inner.cursor,
+            "stylesheets": stylesheets,
+        })
+    }
+
+    // Writes the changes made to a stylesheet in Studio back to its `.rsml` file.
+    fn write_back(&self, snapshot: &Value) -> Result<Value, (u16, String)> {
+        let id = snapshot["id"]
+            .as_str()
+            .ok_or((400, "The stylesheet has no id".to_string()))?;
+
+        // The file is written without holding the lock, as the watcher publishes the rebuild.
+        let (path, compiled) = {
+            let inner = self.inner.lock().unwrap();
+            let published = inner
+                .stylesheets
+                .get(id)
+                .ok_or((404, format!("No stylesheet has the id {:?}", id)))?;
+
+            (published.path.clone(), published.stylesheet.clone())
+        };
+
+        let result = write_back(&path, &compiled, snapshot).map_err(|err| (500, err))?;
+
+        for warning in &result.warnings {
+            self.report(Diagnostic::warning(format!("{} In {:#?}.", warning, path)));
+        }
+
+        Ok(json!({
+            "applied": result.applied,
+            "warnings": result.warnings,
+        }))
+    }
+
+    // Subscribes unless too many subscriptions are already open, such as
+    // from a plugin which keeps reconnecting.
+    fn try_subscribe(&self, cursor: u64, timeout: Duration) -> Option<Value> {
+        if self.subscribers.fetch_add(1, Ordering::SeqCst) >= MAX_SUBSCRIBERS {
+            self.subscribers.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let response = self.subscribe(cursor, timeout);
+        self.subscribers.fetch_sub(1, Ordering::SeqCst);
+
+        Some(response)
+    }
+
+    // Waits until there are changes after the cursor, or the timeout passes.
+    fn subscribe(&self, cursor: u64, timeout: Duration) -> Value {
+        let inner = self.inner.lock().unwrap();
+
+        // Cursors from a previous session are reset straight away.
+        let timeout = match cursor > inner.cursor {
+            true => Duration::ZERO,
+            false => timeout,
+        };
+
+        let (inner, _) = self
+            .changed
+            .wait_timeout_while(inner, timeout, |inner| inner.cursor <= cursor)
+            .unwrap();
+
+        let oldest = inner.cursor - inner.changes.len() as u64;
+
+        // Subscribers which have fallen too far behind need to fetch every stylesheet again.
+        if cursor < oldest || cursor > inner.cursor {
+            return json!({
+                "sessionId": self.session_id,
+                "cursor": inner.cursor,
+                "reset": true,
+                "changes": [],
+            });
+        }
+
+        let changes = inner
+            .changes
+            .iter()
+            .skip((cursor - oldest) as usize)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        json!({
+            "sessionId": self.session_id,
+            "cursor": inner.cursor,
+            "reset": false,
+            "changes": changes,
+        })
+    }
+}
+
+impl Inner {
+    fn push(&mut self, change: Change) {
+        self.changes.push_back(change);
+        self.cursor += 1;
+
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+}
+
+fn spawn_server(state: Arc<ServeState>, port: u16) -> Result<(), String> {
+    let server = Server::http(("127.0.0.1", port)).map_err(|err| {
+        format!(
+            "ERROR: Could not start the server on port {}: {}",
+            port, err
+        )
+    })?;
+
+    let server = Arc::new(server);
+
+    // Subscriptions are held open, so requests are shared between a fixed number of workers.
+    for _ in 0..WORKERS {
+        let server = server.clone();
+        let state = state.clone();
+
+        thread::Builder::new()
+            .name("Server thread".to_owned())
+            .spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(&state, request);
+                }
+            })
+            .map_err(|err| format!("ERROR: Could not start the server: {}", err))?;
+    }
+
+    Ok(())
+}
+
+fn handle_request(state: &ServeState, mut request: Request) {
+    let url = request.url().to_string();
+    let segments = url
+        .trim_start_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    let not_found = || (404, "Not found".to_string());
+
+    let body = match (request.method(), segments.as_slice()) {
+        (Method::Get, ["api", "rsml"]) => Ok(state.info()),
+        (Method::Get, ["api", "stylesheets"]) => Ok(state.stylesheets()),
+
+        // Studio posts a snapshot of a stylesheet in the same shape as its `.model.json`.
+        (Method::Post, ["api", "stylesheets"]) if !is_from_plugin(&request) => Err((
+            403,
+            format!(
+                "Requests which write back need the {} header and no Origin",
+                PLUGIN_HEADER
+            ),
+        )),
+        (Method::Post, ["api", "stylesheets"]) => {
+            let mut snapshot = String::new();
+
+            match request.as_reader().read_to_string(&mut snapshot) {
+                Ok(_) => serde_json::from_str::<Value>(&snapshot)
+                    .map_err(|err| (400, format!("Invalid stylesheet: {}", err)))
+                    .and_then(|snapshot| state.write_back(&snapshot)),
+                Err(err) => Err((400, format!("Could not read the request: {}", err))),
+            }
+        }
+
+        (Method::Get, ["api", "subscribe", cursor]) => match cursor.parse::<u64>() {
+            Ok(cursor) => state
+                .try_subscribe(cursor, SUBSCRIBE_TIMEOUT)
+                .ok_or((503, "Too many subscriptions are open".to_string())),
+            Err(_) => Err(not_found()),
+        },
+
+        _ => Err(not_found()),
+    };
+
+    let response = match body {
+        Ok(body) => Response::from_string(body.to_string()).with_header(json_header()),
+        Err((status, error)) => Response::from_string(json!({ "error": error }).to_string())
+            .with_header(json_header())
+            .with_status_code(status),
+    };
+
+    if let Err(err) = request.respond(response) {
+        state.report(Diagnostic::warning(format!(
+            "Could not respond to a request: {}",
+            err
+        )));
+    }
+}
+
+// Browsers send an `Origin` with requests from web pages and can't add custom headers
+// without a preflight, which is never answered, so a web page can't write to the project.
+fn is_from_plugin(request: &Request) -> bool {
+    let has_header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .any(|header| header.field.equiv(name))
+    };
+
+    has_header(PLUGIN_HEADER) && !has_header("Origin")
+}
+
+fn json_header() -> Header {
+    Header::from_bytes("Content-Type", "application/json").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rbx_types::{Attributes, Variant};
+    use std::{fs, path::Path};
+
+    #[test]
+    fn subscribers_receive_changes_after_their_cursor() {
+        let state = ServeState::new();
+
+        let compiled = |id: &str, name: &str| {
+            let model_json = json!({ "className": "StyleSheet", "id": id, "name": name });
+            state.publish(&OutputEvent::Compiled {
+                path: &Path::new("src").join(id),
+                id,
+                model_json: &model_json.to_string(),
+            });
+        };
+
+        compiled("button.rsml", "first");
+        compiled("label.rsml", "first");
+        compiled("button.rsml", "second");
+
+        let response = state.subscribe(1, Duration::ZERO);
+        assert_eq!(response["cursor"], 3);
+        assert_eq!(response["changes"][0]["id"], "label.rsml");
+        assert_eq!(response["changes"][1]["stylesheet"]["name"], "second");
+
+        state.publish(&OutputEvent::Removed {
+            path: Path::new("src"),
+        });
+
+        let response = state.subscribe(3, Duration::ZERO);
+        assert_eq!(response["changes"].as_array().unwrap().len(), 2);
+        assert_eq!(response["changes"][0]["kind"], "removed");
+        assert_eq!(state.stylesheets()["stylesheets"], json!({}));
+
+        // Cursors from a previous session can't be caught up.
+        assert_eq!(state.subscribe(100, Duration::ZERO)["reset"], true);
+
+        // Subscriptions beyond the limit are refused rather than holding a worker.
+        state.subscribers.store(MAX_SUBSCRIBERS, Ordering::SeqCst);
+        assert!(state.try_subscribe(3, Duration::ZERO).is_none());
+
+        state
+            .subscribers
+            .store(MAX_SUBSCRIBERS - 1, Ordering::SeqCst);
+        assert!(state.try_subscribe(3, Duration::ZERO).is_some());
+        assert_eq!(
+            state.subscribers.load(Ordering::SeqCst),
+            MAX_SUBSCRIBERS - 1
+        );
+    }
+
+    #[test]
+    fn write_back_warnings_are_sent_to_listeners() {
+        let temp = std::env::temp_dir().join("rsml_test_serve_diagnostics");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(&temp).unwrap();
+
+        let path = temp.join("label.rsml");
+        let source = "TextLabel {\n    Text = $Title;\n}\n";
+        fs::write(&path, source).unwrap();
+
+        let stylesheet = |text: &str| {
+            let mut properties = Attributes::new();
+            properties.insert("Text".to_string(), Variant::String(text.to_string()));
+
+            json!({
+                "className": "StyleSheet",
+                "id": "label.rsml",
+                "children": [{
+                    "className": "StyleRule",
+                    "name": "TextLabel",
+                    "properties": { "PropertiesSerialize": Variant::Attributes(properties) },
+                    "children": [],
+                }],
+            })
+        };
+
+        let state = ServeState::new();
+        let diagnostics = Arc::new(Mutex::new(Vec::new()));
+        let listener_diagnostics = diagnostics.clone();
+        state.add_listener(Box::new(move |event| {
+            if let OutputEvent::Diagnostic(diagnostic) = event {
+                listener_diagnostics
+                    .lock()
+                    .unwrap()
+                    .push(diagnostic.to_string());
+            }
+        }));
+
+        state.publish(&OutputEvent::Compiled {
+            path: &path,
+            id: "label.rsml",
+            model_json: &stylesheet("first").to_string(),
+        });
+
+        // Values which aren't literals, such as tokens, are never replaced.
+        let response = state.write_back(&stylesheet("second")).unwrap();
+        assert_eq!(response["applied"], 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), source);
+
+        let diagnostics = diagnostics.lock().unwrap();
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert!(diagnostics[0].starts_with("WARNING: "));
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+}
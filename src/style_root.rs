@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+
+use crate::NormalizePath;
+
+/// A directory of `.rsml` files along with where they are compiled to.
+#[derive(Debug, Clone)]
+pub struct StyleRoot {
+    pub input_dir: PathBuf,
+    pub output_dir: PathBuf,
+
+    /// Prepended to the ids of every stylesheet in this root so they
+    /// don't collide with the ids of other roots.
+    pub id_prefix: String,
+}
+
+impl StyleRoot {
+    pub fn new(input_dir: PathBuf, output_dir: PathBuf, id_prefix: String) -> Self {
+        Self {
+            input_dir,
+            output_dir,
+            id_prefix,
+        }
+    }
+
+    /// Whether the path is an input of this root. An output directory nested
+    /// inside of the input directory is excluded, as it only contains outputs.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.input_dir)
+            && !(self.has_nested_output() && path.starts_with(&self.output_dir))
+    }
+
+    /// Whether the output directory is inside of, but not the same as, the input directory.
+    pub fn has_nested_output(&self) -> bool {
+        self.output_dir != self.input_dir && self.output_dir.starts_with(&self.input_dir)
+    }
+
+    /// The Rojo id for the stylesheet at the specified path, which is `None` if the
+    /// path isn't valid UTF-8. Ids are separated by forward slashes on every platform.
+    pub fn id(&self, path: &Path) -> Option<String> {
+        let relative = path.normalize();
+        let relative = relative.strip_prefix(&self.input_dir).ok()?;
+
+        let mut components = Vec::new();
+        if !self.id_prefix.is_empty() {
+            components.push(self.id_prefix.as_str());
+        }
+
+        for component in relative.components() {
+            components.push(component.as_os_str().to_str()?);
+        }
+
+        Some(components.join("/"))
+    }
+
+    /// Where the `.model.json` for the specified `.rsml` file is written to.
+    pub fn output_path(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.input_dir).ok()?;
+
+        let mut output_path = self.output_dir.join(relative);
+        output_path.set_extension("model.json");
+
+        Some(output_path)
+    }
+
+    /// The directory in the output which mirrors the specified input directory.
+    pub fn output_dir_for(&self, dir: &Path) -> Option<PathBuf> {
+        let relative = dir.strip_prefix(&self.input_dir).ok()?;
+
+        Some(self.output_dir.join(relative).normalize())
+    }
+}
+
+/// Refuses roots whose input directory is inside of a separate output directory,
+/// where its files would be scanned as outputs and could be removed as stale.
+pub fn check_roots(roots: &[StyleRoot]) -> Result<(), String> {
+    for root in roots {
+        if root.output_dir == root.input_dir {
+            continue;
+        }
+
+        if let Some(nested) = roots
+            .iter()
+            .find(|other| other.input_dir.starts_with(&root.output_dir))
+        {
+            return Err(format!(
+                "ERROR: The input directory {:#?} is inside of the output directory {:#?}, choose an output directory outside of it.",
+                nested.input_dir, root.output_dir
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_use_forward_slashes() {
+        let root = StyleRoot::new("/src".into(), "/src".into(), String::new());
+        assert_eq!(
+            root.id(Path::new("/src/nested/button.rsml")).as_deref(),
+            Some("nested/button.rsml")
+        );
+
+        let root = StyleRoot::new("/shared".into(), "/shared".into(), "shared".into());
+        assert_eq!(
+            root.id(Path::new("/shared/nested/button.rsml")).as_deref(),
+            Some("shared/nested/button.rsml")
+        );
+        assert_eq!(root.id(Path::new("/src/button.rsml")), None);
+    }
+
+    #[test]
+    fn nested_output_directories_are_excluded_from_inputs() {
+        let root = StyleRoot::new("/src".into(), "/src/out".into(), String::new());
+
+        assert!(root.has_nested_output());
+        assert!(root.contains(Path::new("/src/button.rsml")));
+        assert!(!root.contains(Path::new("/src/out/button.rsml")));
+        assert!(!root.contains(Path::new("/src/out")));
+        assert!(check_roots(&[root]).is_ok());
+
+        let shared = StyleRoot::new("/src".into(), "/src".into(), String::new());
+        assert!(!shared.has_nested_output());
+        assert!(shared.contains(Path::new("/src/button.rsml")));
+        assert!(check_roots(&[shared]).is_ok());
+    }
+
+    #[test]
+    fn inputs_inside_of_outputs_are_refused() {
+        let root = StyleRoot::new("/dist/src".into(), "/dist".into(), String::new());
+        assert!(check_roots(&[root]).is_err());
+
+        let root = StyleRoot::new("/src".into(), "/out".into(), String::new());
+        let extra = StyleRoot::new("/out/shared".into(), "/out/shared".into(), "shared".into());
+        assert!(check_roots(std::slice::from_ref(&root)).is_ok());
+        assert!(check_roots(&[root, extra]).is_err());
+    }
+}
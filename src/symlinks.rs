@@ -0,0 +1,132 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+/// How symlinks inside of an input directory are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Symlinks are scanned as if their target was inside of the input directory,
+    /// so stylesheets keep the ids of the path they're linked at.
+    #[default]
+    Follow,
+
+    /// Symlinks are ignored.
+    Skip,
+}
+
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Whether the path, or any of its ancestors up to the base, is a symlink.
+pub fn is_through_symlink(path: &Path, base: &Path) -> bool {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != base && ancestor.starts_with(base))
+        .any(is_symlink)
+}
+
+/// Whether following the symlinked directory would scan one of its own ancestors again,
+/// which is the case when its target contains the target of any directory above it.
+pub fn is_symlink_loop(path: &Path, base: &Path) -> bool {
+    let target = match dunce::canonicalize(path) {
+        Ok(target) => target,
+        Err(_) => return false,
+    };
+
+    path.ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.starts_with(base))
+        .filter_map(|ancestor| dunce::canonicalize(ancestor).ok())
+        .any(|ancestor| ancestor.starts_with(&target))
+}
+
+/// The paths a symlinked directory is linked at, mapped to its real location.
+#[derive(Debug, Default)]
+pub struct SymlinkMap {
+    targets: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SymlinkMap {
+    pub fn insert(&mut self, path: &Path, target: PathBuf) {
+        self.targets.retain(|(linked_path, _)| linked_path != path);
+        self.targets.push((path.to_path_buf(), target));
+    }
+
+    /// Forgets every symlink at or beneath the path.
+    pub fn remove_within(&mut self, path: &Path) {
+        self.targets
+            .retain(|(linked_path, _)| !linked_path.starts_with(path));
+    }
+
+    /// The paths which the real path can be reached at through symlinks.
+    pub fn linked_paths(&self, real_path: &Path) -> Vec<PathBuf> {
+        self.targets
+            .iter()
+            .filter_map(|(linked_path, target)| {
+                let relative = real_path.strip_prefix(target).ok()?;
+                Some(linked_path.join(relative))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_real_paths_to_linked_paths() {
+        let mut symlinks = SymlinkMap::default();
+        symlinks.insert(Path::new("/game-a/themes"), PathBuf::from("/shared/themes"));
+        symlinks.insert(Path::new("/game-b/themes"), PathBuf::from("/shared/themes"));
+
+        assert_eq!(
+            symlinks.linked_paths(Path::new("/shared/themes/dark.rsml")),
+            [
+                PathBuf::from("/game-a/themes/dark.rsml"),
+                PathBuf::from("/game-b/themes/dark.rsml")
+            ]
+        );
+        assert!(
+            symlinks
+                .linked_paths(Path::new("/shared/other.rsml"))
+                .is_empty()
+        );
+
+        symlinks.remove_within(Path::new("/game-a"));
+        assert_eq!(
+            symlinks.linked_paths(Path::new("/shared/themes")),
+            [PathBuf::from("/game-b/themes")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detects_symlink_loops() {
+        let temp = std::env::temp_dir().join("rsml_test_symlink_loops");
+
+        let _ = fs::remove_dir_all(&temp);
+        fs::create_dir_all(temp.join("src/nested")).unwrap();
+        fs::create_dir_all(temp.join("shared")).unwrap();
+
+        let temp = dunce::canonicalize(&temp).unwrap();
+        let src = temp.join("src");
+
+        std::os::unix::fs::symlink(&src, src.join("nested/loop")).unwrap();
+        std::os::unix::fs::symlink(&temp, src.join("parent")).unwrap();
+        std::os::unix::fs::symlink(temp.join("shared"), src.join("shared")).unwrap();
+
+        assert!(is_symlink_loop(&src.join("nested/loop"), &src));
+        assert!(is_symlink_loop(&src.join("parent"), &src));
+        assert!(!is_symlink_loop(&src.join("shared"), &src));
+
+        assert!(is_symlink(&src.join("shared")));
+        assert!(is_through_symlink(&src.join("shared/dark.rsml"), &src));
+        assert!(!is_through_symlink(&src.join("nested/button.rsml"), &src));
+
+        let _ = fs::remove_dir_all(&temp);
+    }
+}
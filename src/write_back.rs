@@ -0,0 +1,703 @@
+use std::{ops::Range, path::Path};
+
+use rbx_types::{Attributes, Variant};
+use serde_json::Value;
+
+use crate::{atomic_write::write_atomic, encoding::read_source};
+
+/// A property of a style rule which was changed in Studio.
+#[derive(Debug)]
+pub struct PropertyEdit {
+    /// The selector of each rule leading to the edited rule, along with
+    /// how many rules before it at the same level share that selector.
+    pub rule: Vec<(String, usize)>,
+
+    pub property: String,
+
+    /// The new value, or `None` if the property was removed.
+    pub value: Option<Variant>,
+}
+
+/// The outcome of writing a snapshot back to its `.rsml` file.
+#[derive(Debug, Default)]
+pub struct WriteBack {
+    /// How many properties were written to the file.
+    pub applied: usize,
+
+    /// Changes which couldn't be written to the file.
+    pub warnings: Vec<String>,
+}
+
+/// Finds the property level differences between the compiled stylesheet and the snapshot.
+pub fn diff_stylesheets(compiled: &Value, snapshot: &Value) -> (Vec<PropertyEdit>, Vec<String>) {
+    let mut edits = Vec::new();
+    let mut warnings = Vec::new();
+
+    diff_rules(
+        compiled,
+        snapshot,
+        &mut Vec::new(),
+        &mut edits,
+        &mut warnings,
+    );
+
+    (edits, warnings)
+}
+
+fn diff_rules(
+    compiled: &Value,
+    snapshot: &Value,
+    rule: &mut Vec<(String, usize)>,
+    edits: &mut Vec<PropertyEdit>,
+    warnings: &mut Vec<String>,
+) {
+    let compiled_rules = style_rules(compiled);
+    let snapshot_rules = style_rules(snapshot);
+
+    for (key, snapshot_rule) in &snapshot_rules {
+        let compiled_rule = match compiled_rules.iter().find(|(other, _)| other == key) {
+            Some((_, compiled_rule)) => compiled_rule,
+            None => {
+                warnings.push(format!(
+                    "The rule {:?} was added in Studio, which can't be written back.",
+                    key.0
+                ));
+                continue;
+            }
+        };
+
+        rule.push(key.clone());
+
+        let compiled_properties = rule_properties(compiled_rule);
+        let snapshot_properties = rule_properties(snapshot_rule);
+
+        for (property, value) in snapshot_properties.iter() {
+            if compiled_properties.get(property.as_str()) != Some(value) {
+                edits.push(PropertyEdit {
+                    rule: rule.clone(),
+                    property: property.clone(),
+                    value: Some(value.clone()),
+                });
+            }
+        }
+
+        for (property, _) in compiled_properties.iter() {
+            if snapshot_properties.get(property.as_str()).is_none() {
+                edits.push(PropertyEdit {
+                    rule: rule.clone(),
+                    property: property.clone(),
+                    value: None,
+                });
+            }
+        }
+
+        diff_rules(compiled_rule, snapshot_rule, rule, edits, warnings);
+        rule.pop();
+    }
+
+    for (key, _) in &compiled_rules {
+        if !snapshot_rules.iter().any(|(other, _)| other == key) {
+            warnings.push(format!(
+                "The rule {:?} was removed in Studio, which can't be written back.",
+                key.0
+            ));
+        }
+    }
+}
+
+// The style rules which are children of the instance, keyed by their selector and occurrence.
+fn style_rules(instance: &Value) -> Vec<((String, usize), &Value)> {
+    let mut rules: Vec<((String, usize), &Value)> = Vec::new();
+
+    let children = instance["children"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    for child in children {
+        if child["className"] != "StyleRule" {
+            continue;
+        }
+
+        let selector = child["name"].as_str().unwrap_or_default().to_string();
+        let occurrence = rules
+            .iter()
+            .filter(|((other, _), _)| *other == selector)
+            .count();
+
+        rules.push(((selector, occurrence), child));
+    }
+
+    rules
+}
+
+fn rule_properties(rule: &Value) -> Attributes {
+    match serde_json::from_value(rule["properties"]["PropertiesSerialize"].clone()) {
+        Ok(Variant::Attributes(properties)) => properties,
+        _ => Attributes::new(),
+    }
+}
+
+/// Formats the value as an RSML literal, if it has one.
+pub fn format_value(value: &Variant) -> Option<String> {
+    // Color components are stored from 0 to 1 but written from 0 to 255.
+    let channel = |channel: f32| (channel * 255.0).round().clamp(0.0, 255.0);
+
+    Some(match value {
+        Variant::String(string) => format!("{:?}", string),
+        Variant::Bool(bool) => bool.to_string(),
+        Variant::Int32(number) => number.to_string(),
+        Variant::Int64(number) => number.to_string(),
+        Variant::Float32(number) => number.to_string(),
+        Variant::Float64(number) => number.to_string(),
+        Variant::Color3(color) => format!(
+            "rgb({}, {}, {})",
+            channel(color.r),
+            channel(color.g),
+            channel(color.b)
+        ),
+        Variant::Color3uint8(color) => format!("rgb({}, {}, {})", color.r, color.g, color.b),
+        Variant::UDim(udim) => format!("udim({}, {})", udim.scale, udim.offset),
+        Variant::UDim2(udim2) => format!(
+            "udim2({}, {}, {}, {})",
+            udim2.x.scale, udim2.x.offset, udim2.y.scale, udim2.y.offset
+        ),
+        Variant::Vector2(vector) => format!("vec2({}, {})", vector.x, vector.y),
+        Variant::Vector3(vector) => format!("vec3({}, {}, {})", vector.x, vector.y, vector.z),
+        _ => return None,
+    })
+}
+
+/// Whether the value is written as a single literal, such as `"text"`, `12` or `rgb(0, 0, 0)`,
+/// rather than referencing a token or computing it from an expression.
+fn is_literal(value: &str) -> bool {
+    let bytes = value.as_bytes();
+
+    match bytes.first() {
+        Some(b'"' | b'\'') => return skip_string(bytes, 0) == bytes.len(),
+        Some(b'#') => {
+            return value.len() > 1 && value[1..].chars().all(|char| char.is_ascii_hexdigit());
+        }
+        _ => {}
+    }
+
+    if value == "true" || value == "false" || value.parse::<f64>().is_ok() {
+        return true;
+    }
+
+    let (name, arguments) = match value
+        .strip_suffix(')')
+        .and_then(|value| value.split_once('('))
+    {
+        Some(call) => call,
+        None => return false,
+    };
+
+    matches!(name, "rgb" | "udim" | "udim2" | "vec2" | "vec3")
+        && arguments
+            .split(',')
+            .all(|argument| argument.trim().parse::<f64>().is_ok())
+}
+
+#[derive(Debug)]
+struct Declaration {
+    name: String,
+
+    /// The whole declaration, including its semicolon.
+    span: Range<usize>,
+    value: Range<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    selector: String,
+    header_start: usize,
+    close: usize,
+    declarations: Vec<Declaration>,
+    children: Vec<Block>,
+}
+
+// Splits the source into its rule blocks and their declarations, skipping strings and comments.
+fn parse_blocks(source: &str) -> Block {
+    let bytes = source.as_bytes();
+
+    let mut stack = vec![Block {
+        close: source.len(),
+        ..Default::default()
+    }];
+    let mut statement_start: Option<usize> = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+
+        if bytes[i..].starts_with(b"--") {
+            i = match long_bracket_level(source, i + 2) {
+                Some(level) => skip_long_bracket(source, i + 2, level),
+                None => source[i..]
+                    .find('\n')
+                    .map(|end| i + end)
+                    .unwrap_or(bytes.len()),
+            };
+            continue;
+        }
+
+        if byte.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = *statement_start.get_or_insert(i);
+
+        match byte {
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+
+            b'[' => {
+                if let Some(level) = long_bracket_level(source, i) {
+                    i = skip_long_bracket(source, i, level);
+                    continue;
+                }
+            }
+
+            b';' => {
+                statement_start = None;
+
+                if let Some(declaration) = parse_declaration(source, start..i + 1) {
+                    stack.last_mut().unwrap().declarations.push(declaration);
+                }
+            }
+
+            b'{' => {
+                statement_start = None;
+
+                stack.push(Block {
+                    selector: normalize_selector(&source[start..i]),
+                    header_start: start,
+                    ..Default::default()
+                });
+            }
+
+            b'}' => {
+                statement_start = None;
+
+                // The last declaration in a block doesn't need a semicolon.
+                if start < i
+                    && let Some(declaration) = parse_declaration(source, start..i)
+                {
+                    stack.last_mut().unwrap().declarations.push(declaration);
+                }
+
+                if stack.len() > 1 {
+                    let mut block = stack.pop().unwrap();
+                    block.close = i;
+                    stack.last_mut().unwrap().children.push(block);
+                }
+            }
+
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    // Unclosed blocks are kept so that their declarations can still be edited.
+    while stack.len() > 1 {
+        let mut block = stack.pop().unwrap();
+        block.close = source.len();
+        stack.last_mut().unwrap().children.push(block);
+    }
+
+    stack.pop().unwrap()
+}
+
+fn parse_declaration(source: &str, span: Range<usize>) -> Option<Declaration> {
+    let statement = &source[span.clone()];
+    let (name, _) = statement.split_once('=')?;
+
+    let name = name.trim();
+    let is_identifier = name.chars().enumerate().all(|(i, char)| {
+        char == '_' || char.is_ascii_alphabetic() || (i > 0 && char.is_ascii_digit())
+    });
+
+    if name.is_empty() || !is_identifier || statement[name.len()..].trim_start().starts_with("==") {
+        return None;
+    }
+
+    let equals = span.start + statement.find('=')? + 1;
+    let value = source[equals..span.end].trim_end_matches(';');
+    let value_start = equals + (value.len() - value.trim_start().len());
+    let value_end = equals + value.trim_end().len();
+
+    Some(Declaration {
+        name: name.to_string(),
+        span,
+        value: value_start..value_end.max(value_start),
+    })
+}
+
+fn normalize_selector(selector: &str) -> String {
+    selector.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn skip_string(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\n' => return i,
+            byte if byte == quote => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    bytes.len()
+}
+
+// The level of the long bracket (such as `[==[`) which opens at the index.
+fn long_bracket_level(source: &str, start: usize) -> Option<usize> {
+    let rest = source[start..].strip_prefix('[')?;
+    let level = rest.len() - rest.trim_start_matches('=').len();
+
+    rest[level..].starts_with('[').then_some(level)
+}
+
+fn skip_long_bracket(source: &str, start: usize, level: usize) -> usize {
+    let close = format!("]{}]", "=".repeat(level));
+    let body = start + level + 2;
+
+    source[body..]
+        .find(&close)
+        .map(|end| body + end + close.len())
+        .unwrap_or(source.len())
+}
+
+fn line_start(source: &str, index: usize) -> usize {
+    source[..index].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+fn line_indent(source: &str, index: usize) -> &str {
+    let start = line_start(source, index);
+    let line = &source[start..];
+
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+fn find_block<'a>(root: &'a Block, rule: &[(String, usize)]) -> Option<&'a Block> {
+    rule.iter().try_fold(root, |block, (selector, occurrence)| {
+        let selector = normalize_selector(selector);
+
+        block
+            .children
+            .iter()
+            .filter(|child| child.selector == selector)
+            .nth(*occurrence)
+    })
+}
+
+/// Applies the edits to the source, only touching the declarations which changed.
+pub fn apply_edits(source: &str, edits: &[PropertyEdit]) -> (String, WriteBack) {
+    let root = parse_blocks(source);
+    let mut result = WriteBack::default();
+    let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+
+    for edit in edits {
+        let rule = edit
+            .rule
+            .iter()
+            .map(|(selector, _)| selector.as_str())
+            .collect::<Vec<_>>()
+            .join(" > ");
+
+        let block = match find_block(&root, &edit.rule) {
+            Some(block) => block,
+            None => {
+                result.warnings.push(format!(
+                    "Could not find the rule {:?} to write {:?} to.",
+                    rule, edit.property
+                ));
+                continue;
+            }
+        };
+
+        // Like the compiler, the last declaration of a property is the one which applies.
+        let declaration = block
+            .declarations
+            .iter()
+            .rev()
+            .find(|declaration| declaration.name == edit.property);
+
+        let value = match &edit.value {
+            Some(value) => match format_value(value) {
+                Some(value) => Some(value),
+                None => {
+                    result.warnings.push(format!(
+                        "The value of {:?} in {:?} can't be written as RSML.",
+                        edit.property, rule
+                    ));
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        match (declaration, value) {
+            // Values such as tokens and expressions would be lost by replacing them with a literal.
+            (Some(declaration), Some(_)) if !is_literal(&source[declaration.value.clone()]) => {
+                result.warnings.push(format!(
+                    "The value of {:?} in {:?} isn't a literal, so it wasn't replaced.",
+                    edit.property, rule
+                ));
+                continue;
+            }
+
+            (Some(declaration), Some(value)) => {
+                replacements.push((declaration.value.clone(), value));
+            }
+
+            (Some(declaration), None) => {
+                replacements.push((removal_range(source, declaration), String::new()));
+            }
+
+            (None, Some(value)) => {
+                replacements.push(insertion(source, block, &edit.property, &value));
+            }
+
+            (None, None) => continue,
+        }
+
+        result.applied += 1;
+    }
+
+    replacements.sort_by_key(|(range, _)| range.start);
+
+    let mut patched = String::with_capacity(source.len());
+    let mut cursor = 0;
+
+    for (range, replacement) in replacements {
+        let start = range.start.max(cursor);
+        patched.push_str(&source[cursor..start]);
+        patched.push_str(&replacement);
+        cursor = range.end.max(start);
+    }
+    patched.push_str(&source[cursor..]);
+
+    (patched, result)
+}
+
+// Removes the whole line if the declaration is the only thing on it.
+fn removal_range(source: &str, declaration: &Declaration) -> Range<usize> {
+    let start = line_start(source, declaration.span.start);
+    let end = source[declaration.span.end..]
+        .find('\n')
+        .map(|i| declaration.span.end + i + 1)
+        .unwrap_or(source.len());
+
+    let alone = source[start..declaration.span.start].trim().is_empty()
+        && source[declaration.span.end..end].trim().is_empty();
+
+    match alone {
+        true => start..end,
+        false => declaration.span.clone(),
+    }
+}
+
+// New declarations are added on their own line at the end of the rule.
+fn insertion(source: &str, block: &Block, property: &str, value: &str) -> (Range<usize>, String) {
+    let declaration = format!("{} = {};", property, value);
+    let close_line = line_start(source, block.close);
+
+    // The closing brace shares its line with other code, such as `Frame { }`.
+    if !source[close_line..block.close].trim().is_empty() {
+        let separator = match source[..block.close].ends_with(char::is_whitespace) {
+            true => "",
+            false => " ",
+        };
+        return (
+            block.close..block.close,
+            format!("{}{} ", separator, declaration),
+        );
+    }
+
+    let indent = match block.declarations.last() {
+        Some(last) => line_indent(source, last.span.start).to_string(),
+        None => {
+            let header_indent = line_indent(source, block.header_start);
+            let unit = match header_indent.starts_with('\t') {
+                true => "\t",
+                false => "    ",
+            };
+            format!("{}{}", header_indent, unit)
+        }
+    };
+
+    (
+        close_line..close_line,
+        format!("{}{}\n", indent, declaration),
+    )
+}
+
+/// Writes the differences between the compiled stylesheet and the snapshot to the `.rsml` file.
+pub fn write_back(path: &Path, compiled: &Value, snapshot: &Value) -> Result<WriteBack, String> {
+    let (source, encoding) =
+        read_source(path).map_err(|err| format!("Could not read {:#?}: {}", path, err))?;
+
+    let (edits, warnings) = diff_stylesheets(compiled, snapshot);
+    let (patched, mut result) = apply_edits(&source, &edits);
+    result.warnings.splice(0..0, warnings);
+
+    if patched != source {
+        write_atomic(path, encoding.encode(&patched))
+            .map_err(|err| format!("Could not write {:#?}: {}", path, err))?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rbx_types::Color3uint8;
+
+    fn edit(rule: &[&str], property: &str, value: Option<Variant>) -> PropertyEdit {
+        PropertyEdit {
+            rule: rule
+                .iter()
+                .map(|selector| (selector.to_string(), 0))
+                .collect(),
+            property: property.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn edits_preserve_formatting_and_comments() {
+        let source = "\
+-- Buttons used across the app.
+TextButton {
+    -- The brand colour.
+    BackgroundColor3 = rgb(0, 0, 0); -- Keep in sync with the logo.
+    Text   =   \"Hello; wörld\";
+    Visible = true;
+
+    .Primary {
+        Text = \"Go\";
+    }
+}
+";
+
+        let edits = [
+            edit(
+                &["TextButton"],
+                "BackgroundColor3",
+                Some(Variant::Color3uint8(Color3uint8 {
+                    r: 255,
+                    g: 0,
+                    b: 10,
+                })),
+            ),
+            edit(&["TextButton"], "Visible", None),
+            edit(
+                &["TextButton"],
+                "Name",
+                Some(Variant::String("Button".into())),
+            ),
+            edit(
+                &["TextButton", ".Primary"],
+                "TextSize",
+                Some(Variant::Int32(18)),
+            ),
+        ];
+
+        let (patched, result) = apply_edits(source, &edits);
+
+        assert_eq!(result.applied, 4);
+        assert!(result.warnings.is_empty());
+        assert_eq!(
+            patched,
+            "\
+-- Buttons used across the app.
+TextButton {
+    -- The brand colour.
+    BackgroundColor3 = rgb(255, 0, 10); -- Keep in sync with the logo.
+    Text   =   \"Hello; wörld\";
+
+    .Primary {
+        Text = \"Go\";
+        TextSize = 18;
+    }
+    Name = \"Button\";
+}
+"
+        );
+    }
+
+    #[test]
+    fn only_literal_values_are_replaced() {
+        let source = "\
+Frame {
+    BackgroundColor3 = $Primary;
+    Size = udim2(1, 0, 0, 20 + 4);
+    Name = \"Panel\";
+}
+";
+
+        let edits = [
+            edit(
+                &["Frame"],
+                "BackgroundColor3",
+                Some(Variant::Color3uint8(Color3uint8 { r: 0, g: 0, b: 0 })),
+            ),
+            edit(&["Frame"], "Size", Some(Variant::Int32(0))),
+            edit(&["Frame"], "Name", Some(Variant::String("Card".into()))),
+        ];
+
+        let (patched, result) = apply_edits(source, &edits);
+
+        assert_eq!(result.applied, 1);
+        assert_eq!(result.warnings.len(), 2);
+        assert_eq!(patched, source.replace("\"Panel\"", "\"Card\""));
+
+        assert!(is_literal("rgb(255, 0, 10)"));
+        assert!(is_literal("#ff00aa"));
+        assert!(is_literal("-0.5"));
+        assert!(!is_literal("\"a\" .. \"b\""));
+        assert!(!is_literal("tw(0.2)"));
+    }
+
+    #[test]
+    fn diffs_only_changed_properties() {
+        let stylesheet = |text: &str| {
+            let mut properties = Attributes::new();
+            properties.insert("Text".to_string(), Variant::String(text.to_string()));
+            properties.insert("Visible".to_string(), Variant::Bool(true));
+
+            serde_json::json!({
+                "className": "StyleSheet",
+                "children": [
+                    { "className": "StyleDerive", "name": "Derive" },
+                    {
+                        "className": "StyleRule",
+                        "name": "TextButton",
+                        "properties": {
+                            "PropertiesSerialize": Variant::Attributes(properties),
+                        },
+                        "children": [],
+                    },
+                ],
+            })
+        };
+
+        let (edits, warnings) = diff_stylesheets(&stylesheet("first"), &stylesheet("second"));
+
+        assert!(warnings.is_empty());
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].rule, vec![("TextButton".to_string(), 0)]);
+        assert_eq!(edits[0].value, Some(Variant::String("second".to_string())));
+    }
+}
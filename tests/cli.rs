@@ -1,5 +1,100 @@
-use assert_cmd::Command;
-use std::fs;
+use assert_cmd::{Command, cargo::cargo_bin};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::{Child, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A long running command, such as `watch`, which is killed when dropped.
+struct Running(Child);
+
+impl Running {
+    fn start(dir: &Path, args: &[&str]) -> Self {
+        let child = std::process::Command::new(cargo_bin("rsml-cli"))
+            .current_dir(dir)
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        Self(child)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+    let start = Instant::now();
+
+    while start.elapsed() < Duration::from_secs(10) {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(25));
+    }
+
+    false
+}
+
+// Makes a request to the `serve` command, returning the json body if it responded.
+fn request(
+    port: u16,
+    method: &str,
+    url: &str,
+    headers: &[&str],
+    body: &str,
+) -> Option<serde_json::Value> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    let headers = headers
+        .iter()
+        .map(|header| format!("{}\r\n", header))
+        .collect::<String>();
+
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+        method,
+        url,
+        headers,
+        body.len(),
+        body
+    )
+    .ok()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+
+    let (_, body) = response.split_once("\r\n\r\n")?;
+    serde_json::from_str(body).ok()
+}
+
+fn get(port: u16, url: &str) -> Option<serde_json::Value> {
+    request(port, "GET", url, &[], "")
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_default()
+}
+
+// Saves the file the way many editors do, by renaming a temporary file over it.
+fn atomic_save(path: &Path, contents: &str) {
+    let temp_path = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+
+    fs::write(&temp_path, contents).unwrap();
+    fs::rename(&temp_path, path).unwrap();
+}
 
 #[test]
 fn cli_build_with_relative_path_no_output() {
@@ -7,7 +102,7 @@ fn cli_build_with_relative_path_no_output() {
 
     // Clean up from any previous run.
     let _ = fs::remove_dir_all(&temp);
-    fs::create_dir_all(&temp.join("src")).unwrap();
+    fs::create_dir_all(temp.join("src")).unwrap();
 
     fs::write(temp.join("src/test.rsml"), "").unwrap();
 
@@ -34,3 +129,453 @@ fn cli_build_with_relative_path_no_output() {
     // Clean up.
     let _ = fs::remove_dir_all(&temp);
 }
+
+#[test]
+fn cli_build_removes_temporary_files_left_by_a_crash() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_build_temp_files");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+
+    fs::write(temp.join("src/button.rsml"), "").unwrap();
+    fs::write(temp.join("src/.button.model.json.rsml-tmp"), "{ \"classNa").unwrap();
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src"])
+        .assert()
+        .success();
+
+    assert!(temp.join("src/button.model.json").exists());
+    assert!(!temp.join("src/.button.model.json.rsml-tmp").exists());
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_clean_removes_generated_files() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_clean");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src/nested")).unwrap();
+    fs::write(temp.join("src/nested/button.rsml"), "").unwrap();
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src", "--output", "out"])
+        .assert()
+        .success();
+    assert!(temp.join("out/nested/button.model.json").exists());
+
+    fs::write(temp.join("out/label.model.json"), "{}").unwrap();
+
+    // Only generated files are ever cleaned, so there's nothing for `--force` to do.
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["clean", "src", "--output", "out", "--force"])
+        .assert()
+        .failure();
+    assert!(temp.join("out/nested/button.model.json").exists());
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["clean", "src", "--output", "out"])
+        .assert()
+        .success();
+
+    assert!(!temp.join("out/nested").exists());
+    assert!(!temp.join("out/.rsml-manifest").exists());
+    assert!(temp.join("out/label.model.json").exists());
+    assert!(temp.join("src/nested/button.rsml").exists());
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_build_refuses_to_overwrite_hand_written_files() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_build_conflict");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+    fs::write(temp.join("src/button.rsml"), "").unwrap();
+    fs::write(temp.join("src/button.model.json"), "{}").unwrap();
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src"])
+        .assert()
+        .failure();
+    assert_eq!(read(&temp.join("src/button.model.json")), "{}");
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src", "--force"])
+        .assert()
+        .success();
+    assert!(read(&temp.join("src/button.model.json")).contains("StyleSheet"));
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_build_excludes_nested_output_directory() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_build_nested_output");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+    fs::write(temp.join("src/button.rsml"), "").unwrap();
+
+    for _ in 0..2 {
+        Command::cargo_bin("rsml-cli")
+            .unwrap()
+            .current_dir(&temp)
+            .args(["build", "src", "--output", "src/out"])
+            .assert()
+            .success();
+    }
+
+    assert!(temp.join("src/out/button.model.json").exists());
+    assert!(!temp.join("src/out/out").exists());
+
+    // Outputs must never be scanned for inputs, or inputs cleaned as outputs.
+    let refused = Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src/out", "--output", "src"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&refused.stdout).contains("is inside of the output directory"));
+    assert!(temp.join("src/button.rsml").exists());
+    assert!(!temp.join("src/.rsml-manifest").exists());
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_build_strict_mode_fails_on_unresolved_derive() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_build_strict");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+
+    fs::write(temp.join("src/button.rsml"), "@derive \"./missing\";\n").unwrap();
+    fs::write(
+        temp.join("src/.luaurc"),
+        "{ \"languageMode\": \"nonstrict\" }",
+    )
+    .unwrap();
+
+    // Nonstrict mode only warns about the unresolved derive.
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src"])
+        .assert()
+        .success();
+    assert!(temp.join("src/button.model.json").exists());
+
+    fs::remove_file(temp.join("src/button.model.json")).unwrap();
+    fs::write(temp.join("src/.luaurc"), "{ \"languageMode\": \"strict\" }").unwrap();
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src"])
+        .assert()
+        .failure();
+    assert!(!temp.join("src/button.model.json").exists());
+
+    // The `--mode` flag overrides both the Luaurc and the file's directive.
+    fs::write(
+        temp.join("src/button.rsml"),
+        "--!strict\n@derive \"./missing\";\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("rsml-cli")
+        .unwrap()
+        .current_dir(&temp)
+        .args(["build", "src", "--mode", "nonstrict"])
+        .assert()
+        .success();
+    assert!(temp.join("src/button.model.json").exists());
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_watch_handles_atomic_saves() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_watch_atomic_save");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+
+    let button = temp.join("src/button.rsml");
+    fs::write(&button, "Frame {\n    Name = \"first\";\n}\n").unwrap();
+
+    let _watch = Running::start(&temp, &["watch", "src"]);
+    let output = temp.join("src/button.model.json");
+    assert!(wait_for(|| read(&output).contains("first")));
+
+    atomic_save(&button, "Frame {\n    Name = \"second\";\n}\n");
+    assert!(wait_for(|| read(&output).contains("second")));
+
+    // Saving again right away must still be picked up.
+    atomic_save(&button, "Frame {\n    Name = \"third\";\n}\n");
+    assert!(wait_for(|| read(&output).contains("third")));
+
+    assert!(!temp.join("src/.button.rsml.model.json").exists());
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_watch_handles_renames() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_watch_rename");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+
+    fs::write(temp.join("src/base.rsml"), "").unwrap();
+    fs::write(temp.join("src/button.rsml"), "@derive \"./base\";\n").unwrap();
+
+    let _watch = Running::start(&temp, &["watch", "src", "--output", "out"]);
+    let button_output = temp.join("out/button.model.json");
+    assert!(wait_for(|| read(&button_output).contains("base.rsml")));
+
+    // The old output is removed and the dependant no longer derives the old path.
+    fs::rename(temp.join("src/base.rsml"), temp.join("src/theme.rsml")).unwrap();
+    assert!(wait_for(|| temp.join("out/theme.model.json").exists()
+        && !temp.join("out/base.model.json").exists()
+        && !read(&button_output).contains("base.rsml")));
+
+    // Renaming the file back rebuilds the dependant which was waiting on it.
+    fs::rename(temp.join("src/theme.rsml"), temp.join("src/base.rsml")).unwrap();
+    assert!(wait_for(|| read(&button_output).contains("base.rsml")));
+
+    // Moving a directory moves its outputs too.
+    fs::create_dir_all(temp.join("src/old")).unwrap();
+    fs::write(temp.join("src/old/panel.rsml"), "").unwrap();
+    assert!(wait_for(|| temp.join("out/old/panel.model.json").exists()));
+
+    fs::rename(temp.join("src/old"), temp.join("src/new")).unwrap();
+    assert!(wait_for(
+        || temp.join("out/new/panel.model.json").exists() && !temp.join("out/old").exists()
+    ));
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_serve_publishes_stylesheets_and_changes() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_serve");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+
+    let button = temp.join("src/button.rsml");
+    fs::write(&button, "Frame {\n    Name = \"first\";\n}\n").unwrap();
+
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let _serve = Running::start(&temp, &["serve", "src", "--port", &port.to_string()]);
+
+    let mut info = None;
+    assert!(wait_for(|| {
+        info = get(port, "/api/rsml");
+        info.is_some()
+    }));
+    assert_eq!(info.unwrap()["protocolVersion"], 1);
+
+    let stylesheets = get(port, "/api/stylesheets").unwrap();
+    let stylesheet = &stylesheets["stylesheets"]["button.rsml"];
+    assert_eq!(stylesheet["className"], "StyleSheet");
+    assert!(stylesheet.to_string().contains("first"));
+
+    // Subscribing waits for the next rebuild.
+    let cursor = stylesheets["cursor"].as_u64().unwrap();
+    let subscription =
+        thread::spawn(move || get(port, &format!("/api/subscribe/{}", cursor)).unwrap());
+
+    thread::sleep(Duration::from_millis(200));
+    fs::write(&button, "Frame {\n    Name = \"second\";\n}\n").unwrap();
+
+    let changes = subscription.join().unwrap();
+    assert_eq!(changes["reset"], false);
+    assert_eq!(changes["changes"][0]["kind"], "updated");
+    assert_eq!(changes["changes"][0]["id"], "button.rsml");
+    assert!(
+        changes["changes"][0]["stylesheet"]
+            .to_string()
+            .contains("second")
+    );
+
+    // Edits made in Studio are written back to the file.
+    let mut snapshot = changes["changes"][0]["stylesheet"].clone();
+    snapshot["children"][0]["properties"]["PropertiesSerialize"]["Attributes"]["Name"] =
+        serde_json::json!({ "String": "third" });
+
+    // Requests from web pages, or without the plugin's header, can't write back.
+    for headers in [
+        &["X-RSML-Plugin: 1", "Origin: http://example.com"][..],
+        &[][..],
+    ] {
+        let response = request(
+            port,
+            "POST",
+            "/api/stylesheets",
+            headers,
+            &snapshot.to_string(),
+        )
+        .unwrap();
+        assert!(response["error"].is_string());
+        assert_eq!(read(&button), "Frame {\n    Name = \"second\";\n}\n");
+    }
+
+    let response = request(
+        port,
+        "POST",
+        "/api/stylesheets",
+        &["X-RSML-Plugin: 1"],
+        &snapshot.to_string(),
+    )
+    .unwrap();
+    assert_eq!(response["applied"], 1);
+    assert_eq!(read(&button), "Frame {\n    Name = \"third\";\n}\n");
+
+    let cursor = changes["cursor"].as_u64().unwrap();
+    let changes = get(port, &format!("/api/subscribe/{}", cursor)).unwrap();
+    assert!(
+        changes["changes"][0]["stylesheet"]
+            .to_string()
+            .contains("third")
+    );
+
+    fs::remove_file(&button).unwrap();
+    let cursor = changes["cursor"].as_u64().unwrap();
+    let changes = get(port, &format!("/api/subscribe/{}", cursor)).unwrap();
+    assert_eq!(changes["changes"][0]["kind"], "removed");
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_watch_summarises_session_when_stopped() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_watch_shutdown");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+    fs::write(temp.join("src/button.rsml"), "").unwrap();
+
+    let watch = std::process::Command::new(cargo_bin("rsml-cli"))
+        .current_dir(&temp)
+        .args(["watch", "src"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(wait_for(|| temp.join("src/button.model.json").exists()));
+
+    // Gives the watcher time to start listening for the signal.
+    thread::sleep(Duration::from_millis(200));
+    std::process::Command::new("kill")
+        .args(["-INT", &watch.id().to_string()])
+        .status()
+        .unwrap();
+
+    let output = watch.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(
+        stdout.contains("Rebuilt 1 file(s) with 0 error(s)"),
+        "{}",
+        stdout
+    );
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_watch_exits_when_input_directory_is_removed() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_watch_input_removed");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+    fs::write(temp.join("src/button.rsml"), "").unwrap();
+
+    let mut watch = Running::start(&temp, &["watch", "src"]);
+    assert!(wait_for(|| temp.join("src/button.model.json").exists()));
+
+    fs::remove_dir_all(temp.join("src")).unwrap();
+
+    let mut status = None;
+    assert!(wait_for(|| {
+        status = watch.0.try_wait().unwrap();
+        status.is_some()
+    }));
+    assert!(status.unwrap().success());
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_watch_polls_for_changes() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_watch_poll");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src")).unwrap();
+
+    let button = temp.join("src/button.rsml");
+    fs::write(&button, "Frame {\n    Name = \"first\";\n}\n").unwrap();
+
+    // Native notifications are disabled, so every change is found by polling.
+    let _watch = Running::start(&temp, &["watch", "src", "--poll", "50ms"]);
+    let output = temp.join("src/button.model.json");
+    assert!(wait_for(|| read(&output).contains("first")));
+
+    fs::write(&button, "Frame {\n    Name = \"second\";\n}\n").unwrap();
+    assert!(wait_for(|| read(&output).contains("second")));
+
+    fs::create_dir_all(temp.join("src/nested")).unwrap();
+    fs::write(temp.join("src/nested/panel.rsml"), "").unwrap();
+    assert!(wait_for(|| temp
+        .join("src/nested/panel.model.json")
+        .exists()));
+
+    fs::remove_file(&button).unwrap();
+    assert!(wait_for(|| !output.exists()));
+
+    let _ = fs::remove_dir_all(&temp);
+}
+
+#[test]
+fn cli_watch_regenerates_deleted_outputs() {
+    let temp = std::env::temp_dir().join("rsml_test_cli_watch_regenerate");
+
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(temp.join("src/nested")).unwrap();
+    fs::write(temp.join("src/nested/button.rsml"), "").unwrap();
+
+    let _watch = Running::start(&temp, &["watch", "src", "--output", "out"]);
+    let output = temp.join("out/nested/button.model.json");
+    assert!(wait_for(|| output.exists()));
+
+    // Notifications for a file created and removed within the debounce window cancel out.
+    thread::sleep(Duration::from_millis(200));
+    fs::remove_dir_all(temp.join("out")).unwrap();
+    assert!(wait_for(|| output.exists()));
+
+    fs::write(&output, "{}").unwrap();
+    assert!(wait_for(|| read(&output).contains("StyleSheet")));
+
+    let _ = fs::remove_dir_all(&temp);
+}